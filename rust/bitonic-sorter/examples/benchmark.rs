@@ -1,34 +1,125 @@
-use num_cpus;
-
 use bitonic_sorter::fourth::sort as par_sort;
-use bitonic_sorter::third::sort as seq_sort;
-use bitonic_sorter::utils::{is_sorted_ascending, new_u32_vec};
-use bitonic_sorter::SortOrder;
+use bitonic_sorter::iterative::{
+    par_sort as iter_par_sort, par_sort_by_with, sort as iter_seq_sort, sort_by_with,
+    ExecutionOrder,
+};
+use bitonic_sorter::odd_even::{
+    par_sort as odd_even_par_sort, sort as odd_even_seq_sort, sort_by as odd_even_sort_by,
+};
+use bitonic_sorter::simd::sort_u32 as simd_sort;
+use bitonic_sorter::third::{sort as seq_sort, sort_by as seq_sort_by};
+use bitonic_sorter::utils::{new_vec, par_check_sorted, par_is_permutation_of, Distribution};
+use bitonic_sorter::{SortError, SortOrder};
 
+use std::cell::Cell;
+use std::cmp::Ordering;
 use std::str::FromStr;
 use std::time::Instant;
 use std::{env, f64};
 
+const USAGE: &str = "Usage: benchmark <number of elements in bits> [max number of elements in bits] [--distribution=NAME] [--seed=N]
+
+distributions:
+  uniform (default), sorted, reversed, nearly-sorted[:SWAPS], few-unique[:VALUES],
+  zipf[:VALUES[:EXPONENT]], organ-pipe, sawtooth[:PERIOD]";
+
 fn main() {
+    // --で始まる引数はオプション、それ以外は要素数として受け取る
+    let (options, args): (Vec<String>, Vec<String>) =
+        env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let mut distribution = Distribution::Uniform;
+    let mut seed = 0;
+    for option in options.iter() {
+        if let Some(name) = option.strip_prefix("--distribution=") {
+            distribution = parse_distribution(name).unwrap_or_else(|| usage());
+        } else if let Some(n) = option.strip_prefix("--seed=") {
+            seed = u64::from_str(n).unwrap_or_else(|_| usage());
+        } else {
+            usage();
+        }
+    }
+
     // 1つめのコマンドライン引数を文字列として受け取る
-    if let Some(n) = env::args().nth(1) {
+    if let Some(n) = args.first() {
         // 文字列型からu32に変換を試み、成功したらbitsに束縛
         // もし失敗したらエラーを起こして終了させる
-        let bits = u32::from_str(&n).expect("error parsing argument.");
-        // 順次ソートと並列ソートを実行
-        run_sorts(bits);
+        let bits = u32::from_str(n).expect("error parsing argument.");
+        // 2つめの引数があれば、bitsからその値までの各要素数で測定する
+        // 例: 24 28 -> 2^24, 2^25, ..., 2^28
+        let max_bits = args
+            .get(1)
+            .map(|m| u32::from_str(m).expect("error parsing argument."))
+            .unwrap_or(bits);
+        println!("distribution: {:?}, seed: {}", distribution, seed);
+        for bits in bits..=max_bits {
+            // 順次ソートと並列ソートを実行
+            run_sorts(bits, &Input { distribution, seed });
+        }
     } else {
         // コマンドライン引数が指定されていなかったらヘルプメッセージを出して
         // ステータスコード1で終了する
-        eprintln!(
-            "Usage {} <number of elements in bits>",
-            env::args().nth(0).unwrap()
-        );
-        std::process::exit(1);
+        usage();
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(1);
+}
+
+// 例: "sorted", "nearly-sorted:100", "zipf:1000:1.2"
+fn parse_distribution(value: &str) -> Option<Distribution> {
+    let mut parts = value.split(':');
+    let name = parts.next()?;
+    let params: Vec<&str> = parts.collect();
+    let param = |i: usize, default: usize| match params.get(i) {
+        Some(p) => usize::from_str(p).ok(),
+        None => Some(default),
+    };
+    let distribution = match name {
+        "uniform" => Distribution::Uniform,
+        "sorted" => Distribution::Sorted,
+        "reversed" => Distribution::Reversed,
+        "nearly-sorted" => Distribution::NearlySorted {
+            swaps: param(0, 1000)?,
+        },
+        "few-unique" => Distribution::FewUnique {
+            values: param(0, 16)?,
+        },
+        "zipf" => Distribution::Zipf {
+            values: param(0, 1000)?,
+            // NaNや無限大の指数では分布にならないので受け付けない
+            exponent: match params.get(1) {
+                Some(p) => f64::from_str(p).ok().filter(|e| e.is_finite())?,
+                None => 1.0,
+            },
+        },
+        "organ-pipe" => Distribution::OrganPipe,
+        "sawtooth" => Distribution::Sawtooth {
+            period: param(0, 1024)?,
+        },
+        _ => return None,
+    };
+    let max_params = match distribution {
+        Distribution::Zipf { .. } => 2,
+        Distribution::NearlySorted { .. }
+        | Distribution::FewUnique { .. }
+        | Distribution::Sawtooth { .. } => 1,
+        _ => 0,
+    };
+    if params.len() > max_params {
+        return None;
     }
+    Some(distribution)
 }
 
-fn run_sorts(bits: u32) {
+// ソートする入力の分布とシード値
+struct Input {
+    distribution: Distribution,
+    seed: u64,
+}
+
+fn run_sorts(bits: u32, input: &Input) {
     // 指定されたビット数からデータの要素数を求める
     // 例: 28bit -> 268,435,456
     let len = 2.0_f64.powi(bits as i32) as usize;
@@ -48,21 +139,105 @@ fn run_sorts(bits: u32) {
     );
 
     // 順次ソートを実行して、処理にかかった時間を得る
-    let seq_duration = timed_sort(&seq_sort, len, "seq_sort");
+    let seq_duration = timed_sort(&seq_sort, len, input, "seq_sort");
 
     // 並列ソートを実行して、処理にかかった時間を得る
-    let par_duration = timed_sort(&par_sort, len, "par_sort");
+    let par_duration = timed_sort(&par_sort, len, input, "par_sort");
 
     // 並列ソートが順次ソートに対して何倍速かったのか表示する
     println!("speed up: {:.2}x", seq_duration / par_duration);
+
+    // AVX2で比較交換する並列ソートを実行して、順次ソートに対して何倍速かったのか表示する
+    let simd_duration = timed_sort(&simd_sort, len, input, "simd_sort");
+    println!("simd speed up: {:.2}x", seq_duration / simd_duration);
+
+    // 再帰を使わない実装を実行して、再帰版に対して何倍速かったのか表示する
+    let iter_seq_duration = timed_sort(&iter_seq_sort, len, input, "iter_seq_sort");
+    println!(
+        "iterative speed up (seq): {:.2}x",
+        seq_duration / iter_seq_duration
+    );
+    let iter_par_duration = timed_sort(&iter_par_sort, len, input, "iter_par_sort");
+    println!(
+        "iterative speed up (par): {:.2}x",
+        par_duration / iter_par_duration
+    );
+
+    // 段ごとの実行順序と、キャッシュに収まるタイルごとにまとめる実行順序を比べる
+    // タイルは256KB(u32で65,536要素)にする
+    let blocked = ExecutionOrder::CacheBlocked {
+        tile_len: 256 * 1024 / std::mem::size_of::<u32>(),
+    };
+    let blocked_seq_duration = timed_sort(
+        &|x: &mut [u32], _: &SortOrder| sort_by_with(x, &|a, b| a.cmp(b), blocked),
+        len,
+        input,
+        "iter_seq_sort (cache blocked)",
+    );
+    let blocked_par_duration = timed_sort(
+        &|x: &mut [u32], _: &SortOrder| par_sort_by_with(x, &|a, b| a.cmp(b), blocked),
+        len,
+        input,
+        "iter_par_sort (cache blocked)",
+    );
+    println!("elements per second:");
+    for &(name, duration) in [
+        ("iter_seq_sort (stagewise)", iter_seq_duration),
+        ("iter_seq_sort (cache blocked)", blocked_seq_duration),
+        ("iter_par_sort (stagewise)", iter_par_duration),
+        ("iter_par_sort (cache blocked)", blocked_par_duration),
+    ]
+    .iter()
+    {
+        println!("  {}: {:.3e}", name, len as f64 / (duration / 1e9));
+    }
+
+    // 奇偶マージソートを実行して、バイトニックソートに対して何倍速かったのか表示する
+    let odd_even_seq_duration = timed_sort(&odd_even_seq_sort, len, input, "odd_even_seq_sort");
+    println!(
+        "odd-even speed up (seq): {:.2}x",
+        seq_duration / odd_even_seq_duration
+    );
+    let odd_even_par_duration = timed_sort(&odd_even_par_sort, len, input, "odd_even_par_sort");
+    println!(
+        "odd-even speed up (par): {:.2}x",
+        par_duration / odd_even_par_duration
+    );
+
+    // 比較関数が呼ばれた回数を数えて、比較器の数を比べる
+    // 比較器の数は値によらないので、大きすぎない長さで数える
+    let count_len = len.min(1 << 20);
+    let bitonic_count = count_comparisons(&|x, c| seq_sort_by(x, &c), count_len, input);
+    let odd_even_count = count_comparisons(&|x, c| odd_even_sort_by(x, &c), count_len, input);
+    println!(
+        "comparators for {} integers: bitonic {}, odd-even {} ({:.1}%)",
+        count_len,
+        bitonic_count,
+        odd_even_count,
+        odd_even_count as f64 / bitonic_count as f64 * 100.0
+    );
 }
 
-fn timed_sort<F>(sorter: &F, len: usize, name: &str) -> f64
+fn count_comparisons<S>(sort_by: &S, len: usize, input: &Input) -> usize
 where
-    F: Fn(&mut [u32], &SortOrder) -> Result<(), String>,
+    S: Fn(&mut [u32], &dyn Fn(&u32, &u32) -> Ordering) -> Result<(), SortError>,
 {
-    // 要素数lenのu32型ベクタを生成する
-    let mut x = new_u32_vec(len);
+    let mut x = new_vec(len, input.distribution, input.seed);
+    let count = Cell::new(0);
+    sort_by(&mut x, &|a, b| {
+        count.set(count.get() + 1);
+        a.cmp(b)
+    })
+    .expect("Failed to start: ");
+    count.get()
+}
+
+fn timed_sort<F>(sorter: &F, len: usize, input: &Input, name: &str) -> f64
+where
+    F: Fn(&mut [u32], &SortOrder) -> Result<(), SortError>,
+{
+    // 指定された分布に従う、要素数lenのu32型ベクタを生成する
+    let mut x = new_vec(len, input.distribution, input.seed);
 
     // sorter関数を呼び出すことでソートを実行する
     // かかった時間(dur)を記録する
@@ -80,7 +255,17 @@ where
     );
 
     // ソートの結果が正しいか検証
-    assert!(is_sorted_ascending(&x));
+    // 要素数が多いと検証に時間がかかるので並列版を使う
+    // 入力は同じシード値から生成し直して、要素が失われていないことも確認する
+    if let Err(i) = par_check_sorted(&x, &|a: &u32, b: &u32| a.cmp(b)) {
+        panic!("{}: not sorted at index {}", name, i);
+    }
+    let original: Vec<u32> = new_vec(len, input.distribution, input.seed);
+    assert!(
+        par_is_permutation_of(&original, &x),
+        "{}: not a permutation of the input",
+        name
+    );
 
     nano_secs
 }
@@ -0,0 +1,43 @@
+use bitonic_sorter::network::Network;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+fn main() {
+    // 1つめのコマンドライン引数は要素数、2つめは出力先のディレクトリ(省略時はカレント)
+    if let Some(n) = env::args().nth(1) {
+        let len = usize::from_str(&n).expect("error parsing argument.");
+        let dir = env::args().nth(2).unwrap_or_else(|| ".".to_string());
+        write_network(len, Path::new(&dir));
+    } else {
+        eprintln!(
+            "Usage {} <number of elements> [output directory]",
+            env::args().next().unwrap()
+        );
+        std::process::exit(1);
+    }
+}
+
+fn write_network(len: usize, dir: &Path) {
+    let network = Network::bitonic(len);
+    println!(
+        "bitonic network for {} elements: {} comparators in {} layers",
+        len,
+        network.comparators().len(),
+        network.layers().len()
+    );
+
+    // network_<n>.json, network_<n>.dot, network_<n>.svgを書き出す
+    let outputs = [
+        ("json", network.to_json()),
+        ("dot", network.to_dot()),
+        ("svg", network.to_svg()),
+    ];
+    for (extension, contents) in outputs.iter() {
+        let path = dir.join(format!("network_{}.{}", len, extension));
+        fs::write(&path, contents).expect("error writing file.");
+        println!("wrote {}", path.display());
+    }
+}
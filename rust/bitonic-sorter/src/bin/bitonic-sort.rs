@@ -0,0 +1,816 @@
+// GNU sortの一部の機能をまねた、行をソートするコマンド
+// ファイル(省略したときや-のときは標準入力)から行を読み込み、fourth::sort_byでソートする
+//
+// bitonic-sort [-n] [-r] [-u] [-k POS1[,POS2]] [-t SEP] [-o FILE] [--parallel=N] [FILE]...
+// bitonic-sort csv [-k COLUMN[:TYPE][:ORDER]]... [-o FILE] [--parallel=N] [FILE]
+// bitonic-sort jsonl -k PATH [-r] [--missing=first|last] [-o FILE] [--parallel=N] [FILE]...
+//
+// 比較はバイト列としての比較(LC_ALL=Cのsortと同じ)で、
+// キーが等しい行は、-uがなければ行全体で比較して順を決める
+
+use bitonic_sorter::csv::{self, ColumnType, SortKey};
+use bitonic_sorter::json::{self, MissingKeys};
+use bitonic_sorter::{fourth, SortOrder};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::process;
+
+const USAGE: &str = "Usage: bitonic-sort [-n] [-r] [-u] [-k POS1[,POS2]] [-t SEP] [-o FILE] [--parallel=N] [FILE]...
+
+  -n             compare the key as a number
+  -r             reverse the result of comparisons
+  -u             output only the first of lines with equal keys
+  -k POS1[,POS2] use fields POS1 through POS2 (or the end of line) as the key
+  -t SEP         use the character SEP as the field separator instead of blanks
+  -o FILE        write the result to FILE instead of standard output
+  --parallel=N   sort with N threads
+
+Usage: bitonic-sort csv [-k COLUMN[:TYPE][:ORDER]]... [-o FILE] [--parallel=N] [FILE]
+
+  Sort CSV records, keeping the header row first.
+  -k COLUMN[:TYPE][:ORDER]
+                 sort by COLUMN, a header name or a number counted from 1
+                 TYPE is int, float, str (default) or date (YYYY-MM-DD)
+                 ORDER is asc (default) or desc
+                 repeat -k to break ties with more columns
+
+Usage: bitonic-sort jsonl -k PATH [-r] [--missing=first|last] [-o FILE] [--parallel=N] [FILE]...
+
+  Sort JSON Lines by the value at PATH, writing each line back unchanged.
+  Blank lines are placed with the lines without the key.
+  -k PATH        sort by the value at PATH, such as .request.latency_ms
+                 numbers compare numerically and strings lexicographically
+  -r             sort in descending order
+  --missing=WHERE
+                 put lines without the key first or last (default)";
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    numeric: bool,
+    reverse: bool,
+    unique: bool,
+    // 1から数えたフィールドの位置。終わりがNoneなら行末まで
+    key: Option<(usize, Option<usize>)>,
+    separator: Option<u8>,
+    output: Option<String>,
+    parallel: Option<usize>,
+    files: Vec<String>,
+}
+
+// csvのオプション
+#[derive(Debug, Default, PartialEq)]
+struct CsvOptions {
+    // 列の指定、型、順序
+    keys: Vec<(String, ColumnType, SortOrder)>,
+    output: Option<String>,
+    parallel: Option<usize>,
+    file: Option<String>,
+}
+
+// jsonlのオプション
+#[derive(Debug, PartialEq)]
+struct JsonLinesOptions {
+    path: Option<String>,
+    order: SortOrder,
+    missing: MissingKeys,
+    output: Option<String>,
+    parallel: Option<usize>,
+    files: Vec<String>,
+}
+
+impl Default for JsonLinesOptions {
+    fn default() -> Self {
+        JsonLinesOptions {
+            path: None,
+            order: SortOrder::Ascending,
+            missing: MissingKeys::Last,
+            output: None,
+            parallel: None,
+            files: Vec::new(),
+        }
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+    // 最初の引数がcsvやjsonlなら、CSVやJSON Linesをソートする
+    let result = match args.peek().map(String::as_str) {
+        Some("csv") => {
+            args.next();
+            parse_csv_args(args).map(|options| run_csv(&options))
+        }
+        Some("jsonl") => {
+            args.next();
+            parse_json_lines_args(args).map(|options| run_json_lines(&options))
+        }
+        _ => parse_args(args).map(|options| run(&options)),
+    };
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => {
+            eprintln!("bitonic-sort: {}", e);
+            process::exit(2);
+        }
+        Err(message) => {
+            eprintln!("bitonic-sort: {}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+fn run(options: &Options) -> io::Result<()> {
+    set_threads(options.parallel)?;
+    let contents = read_inputs(&options.files)?;
+    let mut lines: Vec<&[u8]> = contents.iter().flat_map(|c| split_lines(c)).collect();
+    sort_lines(&mut lines, options);
+
+    let mut writer = create_output(&options.output)?;
+    for line in lines {
+        writer.write_all(line)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+fn run_csv(options: &CsvOptions) -> io::Result<()> {
+    set_threads(options.parallel)?;
+    let files: Vec<String> = options.file.iter().cloned().collect();
+    let contents = read_inputs(&files)?.remove(0);
+    let invalid_data = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let input = String::from_utf8(contents).map_err(|e| invalid_data(e.to_string()))?;
+    let mut table = csv::parse(&input).map_err(|e| invalid_data(e.to_string()))?;
+    let keys = options
+        .keys
+        .iter()
+        .map(|(column, column_type, order)| {
+            let column = resolve_column(&table.header, column).map_err(invalid_data)?;
+            Ok(SortKey::new(column, *column_type, *order))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    csv::sort_table(&mut table, &keys).map_err(|e| invalid_data(e.to_string()))?;
+
+    let mut writer = create_output(&options.output)?;
+    writer.write_all(csv::write(&table).as_bytes())?;
+    writer.flush()
+}
+
+fn run_json_lines(options: &JsonLinesOptions) -> io::Result<()> {
+    set_threads(options.parallel)?;
+    let contents = read_inputs(&options.files)?;
+    let mut writer = create_output(&options.output)?;
+    write_json_lines(&mut writer, &contents, options)?;
+    writer.flush()
+}
+
+// 行を並べ替えて書き出す。出力は入力の行をバイト単位でそのまま並べ替えたものになる
+// 最後の行の後の改行は、入力の末尾に改行があったときだけ書く
+fn write_json_lines<W: Write>(
+    writer: &mut W,
+    contents: &[Vec<u8>],
+    options: &JsonLinesOptions,
+) -> io::Result<()> {
+    let invalid_data = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut lines = contents
+        .iter()
+        .flat_map(|c| split_lines(c))
+        .enumerate()
+        .map(|(i, line)| {
+            std::str::from_utf8(line).map_err(|e| invalid_data(format!("{} (line: {})", e, i + 1)))
+        })
+        .collect::<io::Result<Vec<&str>>>()?;
+    let path = options.path.as_deref().unwrap_or(".");
+    json::sort_json_lines(&mut lines, path, &options.order, options.missing)
+        .map_err(|e| invalid_data(e.to_string()))?;
+
+    let final_newline = contents
+        .iter()
+        .rev()
+        .find(|c| !c.is_empty())
+        .is_some_and(|c| c.ends_with(b"\n"));
+    for (i, line) in lines.iter().enumerate() {
+        writer.write_all(line.as_bytes())?;
+        if i + 1 < lines.len() || final_newline {
+            writer.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
+
+fn set_threads(parallel: Option<usize>) -> io::Result<()> {
+    if let Some(threads) = parallel {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+// 出力先のファイルを指定しなかったときは標準出力に書き出す
+fn create_output(output: &Option<String>) -> io::Result<BufWriter<Box<dyn Write>>> {
+    let output: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    Ok(BufWriter::new(output))
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            options.files.extend(args.by_ref());
+        } else if arg == "--help" {
+            println!("{}", USAGE);
+            process::exit(0);
+        } else if arg.starts_with("--parallel") {
+            let value = match arg.strip_prefix("--parallel=") {
+                Some(value) => value.to_string(),
+                None if arg == "--parallel" => next_value(&mut args, "--parallel")?,
+                None => return Err(format!("unknown option: {}", arg)),
+            };
+            match value.parse() {
+                Ok(n) if n > 0 => options.parallel = Some(n),
+                _ => return Err(format!("invalid number of threads: {}", value)),
+            }
+        } else if arg.starts_with('-') && arg.len() > 1 {
+            for (flag, value) in short_options(&arg, "kto", &mut args)? {
+                match (flag, value) {
+                    ('n', _) => options.numeric = true,
+                    ('r', _) => options.reverse = true,
+                    ('u', _) => options.unique = true,
+                    ('k', Some(value)) => options.key = Some(parse_key(&value)?),
+                    ('t', Some(value)) => options.separator = Some(parse_separator(&value)?),
+                    ('o', value) => options.output = value,
+                    (flag, _) => return Err(format!("unknown option: -{}", flag)),
+                }
+            }
+        } else {
+            options.files.push(arg);
+        }
+    }
+    Ok(options)
+}
+
+fn parse_csv_args<I: Iterator<Item = String>>(mut args: I) -> Result<CsvOptions, String> {
+    let mut options = CsvOptions::default();
+    while let Some(arg) = args.next() {
+        let value = |args: &mut I, option: &str| match arg.strip_prefix(option) {
+            Some("") => next_value(args, option),
+            Some(value) => Ok(value.trim_start_matches('=').to_string()),
+            None => unreachable!(),
+        };
+        if arg == "--help" {
+            println!("{}", USAGE);
+            process::exit(0);
+        } else if arg.starts_with("--parallel") {
+            let threads = value(&mut args, "--parallel")?;
+            match threads.parse() {
+                Ok(n) if n > 0 => options.parallel = Some(n),
+                _ => return Err(format!("invalid number of threads: {}", threads)),
+            }
+        } else if arg.starts_with('-') && arg.len() > 1 {
+            for (flag, value) in short_options(&arg, "ko", &mut args)? {
+                match (flag, value) {
+                    ('k', Some(key)) => options.keys.push(parse_csv_key(&key)?),
+                    ('o', value) => options.output = value,
+                    (flag, _) => return Err(format!("unknown option: -{}", flag)),
+                }
+            }
+        } else if options.file.is_none() {
+            options.file = Some(arg);
+        } else {
+            return Err("csv accepts only one input file".to_string());
+        }
+    }
+    Ok(options)
+}
+
+fn parse_json_lines_args<I: Iterator<Item = String>>(
+    mut args: I,
+) -> Result<JsonLinesOptions, String> {
+    let mut options = JsonLinesOptions::default();
+    while let Some(arg) = args.next() {
+        let value = |args: &mut I, option: &str| match arg.strip_prefix(option) {
+            Some("") => next_value(args, option),
+            Some(value) => Ok(value.trim_start_matches('=').to_string()),
+            None => unreachable!(),
+        };
+        if arg == "--help" {
+            println!("{}", USAGE);
+            process::exit(0);
+        } else if arg.starts_with("--parallel") {
+            let threads = value(&mut args, "--parallel")?;
+            match threads.parse() {
+                Ok(n) if n > 0 => options.parallel = Some(n),
+                _ => return Err(format!("invalid number of threads: {}", threads)),
+            }
+        } else if arg.starts_with("--missing") {
+            options.missing = match value(&mut args, "--missing")?.as_str() {
+                "first" => MissingKeys::First,
+                "last" => MissingKeys::Last,
+                other => return Err(format!("invalid placement of missing keys: {}", other)),
+            };
+        } else if arg.starts_with('-') && arg.len() > 1 {
+            for (flag, value) in short_options(&arg, "ko", &mut args)? {
+                match (flag, value) {
+                    ('r', _) => options.order = SortOrder::Descending,
+                    ('k', value) => options.path = value,
+                    ('o', value) => options.output = value,
+                    (flag, _) => return Err(format!("unknown option: -{}", flag)),
+                }
+            }
+        } else {
+            options.files.push(arg);
+        }
+    }
+    if options.path.is_none() {
+        return Err("jsonl requires a key path (-k PATH)".to_string());
+    }
+    Ok(options)
+}
+
+// 例: "price:float:desc", "name", "3:int"
+// 列名に:が含まれていてもよいように、型と順序は後ろから取り出す
+fn parse_csv_key(value: &str) -> Result<(String, ColumnType, SortOrder), String> {
+    let mut column = value;
+    let mut order = SortOrder::Ascending;
+    let mut column_type = ColumnType::String;
+    if let Some(i) = column.rfind(':') {
+        let parsed = match &column[i + 1..] {
+            "asc" => Some(SortOrder::Ascending),
+            "desc" => Some(SortOrder::Descending),
+            _ => None,
+        };
+        if let Some(parsed) = parsed {
+            order = parsed;
+            column = &column[..i];
+        }
+    }
+    if let Some(i) = column.rfind(':') {
+        let parsed = match &column[i + 1..] {
+            "int" => Some(ColumnType::Integer),
+            "float" => Some(ColumnType::Float),
+            "str" => Some(ColumnType::String),
+            "date" => Some(ColumnType::Date),
+            _ => None,
+        };
+        if let Some(parsed) = parsed {
+            column_type = parsed;
+            column = &column[..i];
+        }
+    }
+    if column.is_empty() {
+        return Err(format!("invalid key: {}", value));
+    }
+    Ok((column.to_string(), column_type, order))
+}
+
+// ヘッダの名前を優先し、一致する名前がなければ1から数えた列番号とみなす
+fn resolve_column(header: &[String], column: &str) -> Result<usize, String> {
+    if let Some(i) = header.iter().position(|h| h == column) {
+        return Ok(i);
+    }
+    match column.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= header.len() => Ok(n - 1),
+        _ => Err(format!("unknown column: {}", column)),
+    }
+}
+
+fn next_value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("option requires an argument: {}", option))
+}
+
+// -nruのようにまとめた短いオプションを1文字ずつ取り出す
+// takes_valueに含まれるオプションの値は、同じ引数の残りか次の引数になる
+// 例: "-rk.a" -> [('r', None), ('k', Some(".a"))]
+fn short_options<I: Iterator<Item = String>>(
+    arg: &str,
+    takes_value: &str,
+    args: &mut I,
+) -> Result<Vec<(char, Option<String>)>, String> {
+    let flags = &arg[1..];
+    let mut options = Vec::new();
+    for (i, flag) in flags.char_indices() {
+        if takes_value.contains(flag) {
+            let rest = &flags[i + flag.len_utf8()..];
+            let value = if rest.is_empty() {
+                next_value(args, &format!("-{}", flag))?
+            } else {
+                rest.to_string()
+            };
+            options.push((flag, Some(value)));
+            break;
+        }
+        options.push((flag, None));
+    }
+    Ok(options)
+}
+
+// 例: "2" -> (2, None), "2,3" -> (2, Some(3))
+fn parse_key(value: &str) -> Result<(usize, Option<usize>), String> {
+    let field = |s: &str| match s.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid key: {}", value)),
+    };
+    match value.find(',') {
+        Some(i) => {
+            let (start, end) = (field(&value[..i])?, field(&value[i + 1..])?);
+            if end < start {
+                return Err(format!("invalid key: {}", value));
+            }
+            Ok((start, Some(end)))
+        }
+        None => Ok((field(value)?, None)),
+    }
+}
+
+// 区切り文字は1バイトの文字に限る
+fn parse_separator(value: &str) -> Result<u8, String> {
+    match value.as_bytes() {
+        [b] => Ok(*b),
+        _ => Err(format!("the separator must be a single byte: {}", value)),
+    }
+}
+
+// ファイルを指定しなかったときや、-を指定したときは標準入力から読み込む
+fn read_inputs(files: &[String]) -> io::Result<Vec<Vec<u8>>> {
+    let stdin = [String::from("-")];
+    let files = if files.is_empty() { &stdin[..] } else { files };
+    let mut contents = Vec::with_capacity(files.len());
+    for file in files.iter() {
+        let mut buffer = Vec::new();
+        if file == "-" {
+            io::stdin().read_to_end(&mut buffer)?;
+        } else {
+            File::open(file)
+                .and_then(|mut f| f.read_to_end(&mut buffer))
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file, e)))?;
+        }
+        contents.push(buffer);
+    }
+    Ok(contents)
+}
+
+// 改行で区切る。末尾の改行の後には行がないものとみなす
+fn split_lines(contents: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let contents = contents.strip_suffix(b"\n").unwrap_or(contents);
+    contents
+        .split(|&b| b == b'\n')
+        .take(if contents.is_empty() { 0 } else { usize::MAX })
+}
+
+fn sort_lines(lines: &mut Vec<&[u8]>, options: &Options) {
+    let comparator = |a: &&[u8], b: &&[u8]| compare(a, b, options);
+    if options.unique {
+        // キーが等しい行を入力の順のまま並べてから、先頭の1行だけを残す
+        // fourth::sort_byは安定ではないので、安定ソートを使う
+        fourth::stable_sort_by(lines, &comparator)
+            .expect("fourth::stable_sort_by accepts any length");
+        lines.dedup_by(|a, b| compare_keys(a, b, options) == Ordering::Equal);
+    } else {
+        fourth::sort_by(lines, &comparator).expect("fourth::sort_by accepts any length");
+    }
+}
+
+fn compare(a: &[u8], b: &[u8], options: &Options) -> Ordering {
+    match compare_keys(a, b, options) {
+        // -uのときは、キーが等しい行は等しいものとして扱う
+        Ordering::Equal if !options.unique => {
+            let ordering = a.cmp(b);
+            if options.reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        }
+        ordering => ordering,
+    }
+}
+
+fn compare_keys(a: &[u8], b: &[u8], options: &Options) -> Ordering {
+    let (a, b) = (key(a, options), key(b, options));
+    let ordering = if options.numeric {
+        parse_number(a)
+            .partial_cmp(&parse_number(b))
+            .unwrap_or(Ordering::Equal)
+    } else {
+        a.cmp(b)
+    };
+    if options.reverse {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
+
+// -kで指定したフィールドの範囲を、区切り文字を含めたまま取り出す
+// 指定がなければ行全体、フィールドが足りなければ空になる
+fn key<'a>(line: &'a [u8], options: &Options) -> &'a [u8] {
+    let (start, end) = match options.key {
+        Some(key) => key,
+        None => return line,
+    };
+    let fields = field_ranges(line, options.separator);
+    match fields.get(start - 1) {
+        Some(&(from, _)) => {
+            let to = end
+                .and_then(|end| fields.get(end - 1).or_else(|| fields.last()))
+                .map_or(line.len(), |&(_, to)| to);
+            &line[from..to]
+        }
+        None => &line[line.len()..],
+    }
+}
+
+// 各フィールドの[開始, 終了)の位置
+// 区切り文字がなければ、空白とタブでない文字の並びごとに区切る
+// GNU sortと同じく、フィールドはその前にある空白とタブを含む(-bで読み飛ばす機能はない)
+fn field_ranges(line: &[u8], separator: Option<u8>) -> Vec<(usize, usize)> {
+    let mut fields = Vec::new();
+    match separator {
+        Some(separator) => {
+            let mut from = 0;
+            for (i, &b) in line.iter().enumerate() {
+                if b == separator {
+                    fields.push((from, i));
+                    from = i + 1;
+                }
+            }
+            fields.push((from, line.len()));
+        }
+        None => {
+            let is_blank = |b: u8| b == b' ' || b == b'\t';
+            let mut i = 0;
+            while i < line.len() {
+                let from = i;
+                while i < line.len() && is_blank(line[i]) {
+                    i += 1;
+                }
+                while i < line.len() && !is_blank(line[i]) {
+                    i += 1;
+                }
+                fields.push((from, i));
+            }
+        }
+    }
+    fields
+}
+
+// 先頭の空白を読み飛ばし、符号、整数部、小数部からなる数を読む
+// 数として読めない部分は無視し、数が1つもなければ0とみなす
+fn parse_number(key: &[u8]) -> f64 {
+    let key = match key.iter().position(|&b| b != b' ' && b != b'\t') {
+        Some(i) => &key[i..],
+        None => return 0.0,
+    };
+    let mut end = 0;
+    if key.first() == Some(&b'-') {
+        end = 1;
+    }
+    while end < key.len() && key[end].is_ascii_digit() {
+        end += 1;
+    }
+    if end < key.len() && key[end] == b'.' {
+        end += 1;
+        while end < key.len() && key[end].is_ascii_digit() {
+            end += 1;
+        }
+    }
+    std::str::from_utf8(&key[..end])
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        key, parse_args, parse_csv_args, parse_json_lines_args, parse_number, resolve_column,
+        sort_lines, split_lines, write_json_lines, CsvOptions, JsonLinesOptions, Options,
+    };
+    use bitonic_sorter::csv::ColumnType;
+    use bitonic_sorter::json::MissingKeys;
+    use bitonic_sorter::SortOrder::*;
+
+    fn args(s: &str) -> Result<Options, String> {
+        parse_args(s.split_whitespace().map(String::from))
+    }
+
+    fn sorted(input: &str, options: &Options) -> String {
+        let mut lines: Vec<&[u8]> = split_lines(input.as_bytes()).collect();
+        sort_lines(&mut lines, options);
+        lines
+            .iter()
+            .map(|line| String::from_utf8_lossy(line) + "\n")
+            .collect()
+    }
+
+    #[test]
+    fn parse_flags() {
+        let options = args("-nr -k 2,3 -t: -ofile --parallel=4 a.txt -- -b.txt").unwrap();
+        assert_eq!(
+            options,
+            Options {
+                numeric: true,
+                reverse: true,
+                unique: false,
+                key: Some((2, Some(3))),
+                separator: Some(b':'),
+                output: Some("file".to_string()),
+                parallel: Some(4),
+                files: vec!["a.txt".to_string(), "-b.txt".to_string()],
+            }
+        );
+        assert_eq!(args("-u -k3 -").unwrap().key, Some((3, None)));
+        assert!(args("-x").is_err());
+        assert!(args("-k 0").is_err());
+        assert!(args("-k 3,2").is_err());
+        assert!(args("-t ab").is_err());
+        assert!(args("--parallel=0").is_err());
+        assert!(args("-o").is_err());
+    }
+
+    #[test]
+    fn extract_keys() {
+        let blank = Options {
+            key: Some((2, None)),
+            ..Options::default()
+        };
+        // 区切り文字がなければ、フィールドの前の空白もキーに含める
+        assert_eq!(key(b"  a  b c", &blank), b"  b c");
+        assert_eq!(key(b"a\t b ", &blank), b"\t b ");
+        assert_eq!(key(b"a", &blank), b"");
+        let colon = Options {
+            key: Some((2, Some(3))),
+            separator: Some(b':'),
+            ..Options::default()
+        };
+        assert_eq!(key(b"a:b::d", &colon), b"b:");
+        assert_eq!(key(b"a:b", &colon), b"b");
+    }
+
+    #[test]
+    fn parse_numbers() {
+        assert_eq!(parse_number(b" -12.5kg"), -12.5);
+        assert_eq!(parse_number(b"42"), 42.0);
+        assert_eq!(parse_number(b"abc"), 0.0);
+        assert_eq!(parse_number(b""), 0.0);
+    }
+
+    #[test]
+    fn sort_like_gnu_sort() {
+        let input = "10 pear\n9 apple\n10 fig\n-1 kiwi\n9 apple\n";
+        assert_eq!(
+            sorted(input, &Options::default()),
+            "-1 kiwi\n10 fig\n10 pear\n9 apple\n9 apple\n"
+        );
+        let numeric = Options {
+            numeric: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            sorted(input, &numeric),
+            "-1 kiwi\n9 apple\n9 apple\n10 fig\n10 pear\n"
+        );
+        // 数が等しい行は、キーが等しいので1行だけ残る
+        let unique_reverse = Options {
+            numeric: true,
+            reverse: true,
+            unique: true,
+            key: Some((1, Some(1))),
+            ..Options::default()
+        };
+        assert_eq!(sorted(input, &unique_reverse).lines().count(), 3);
+        let by_name = Options {
+            key: Some((2, None)),
+            ..Options::default()
+        };
+        assert_eq!(
+            sorted(input, &by_name),
+            "9 apple\n9 apple\n10 fig\n-1 kiwi\n10 pear\n"
+        );
+    }
+
+    #[test]
+    fn unique_keeps_first_input_line() {
+        // キーが等しい行のうち、入力で最初に現れた行が残る
+        let input: String = (0..100).map(|i| format!("{} line{}\n", i % 3, i)).collect();
+        let unique = Options {
+            unique: true,
+            key: Some((1, Some(1))),
+            ..Options::default()
+        };
+        assert_eq!(sorted(&input, &unique), "0 line0\n1 line1\n2 line2\n");
+        let unique_reverse = Options {
+            reverse: true,
+            ..unique
+        };
+        assert_eq!(
+            sorted(&input, &unique_reverse),
+            "2 line2\n1 line1\n0 line0\n"
+        );
+    }
+
+    #[test]
+    fn parse_csv_flags() {
+        let options = parse_csv_args(
+            "-k date:date:desc -kprice:float -k a:b -o out.csv data.csv"
+                .split(' ')
+                .map(String::from),
+        )
+        .unwrap();
+        assert_eq!(
+            options,
+            CsvOptions {
+                keys: vec![
+                    ("date".to_string(), ColumnType::Date, Descending),
+                    ("price".to_string(), ColumnType::Float, Ascending),
+                    ("a:b".to_string(), ColumnType::String, Ascending),
+                ],
+                output: Some("out.csv".to_string()),
+                parallel: None,
+                file: Some("data.csv".to_string()),
+            }
+        );
+        assert!(
+            parse_csv_args(vec!["a.csv".to_string(), "b.csv".to_string()].into_iter()).is_err()
+        );
+        assert!(parse_csv_args(vec!["-k".to_string(), ":desc".to_string()].into_iter()).is_err());
+        assert!(parse_csv_args(vec!["-r".to_string()].into_iter()).is_err());
+
+        let header: Vec<String> = vec!["2".to_string(), "name".to_string()];
+        assert_eq!(resolve_column(&header, "name"), Ok(1));
+        // 列名が数字なら、列番号より列名を優先する
+        assert_eq!(resolve_column(&header, "2"), Ok(0));
+        assert_eq!(resolve_column(&header, "1"), Ok(0));
+        assert!(resolve_column(&header, "3").is_err());
+    }
+
+    #[test]
+    fn json_lines_keep_final_line_ending() {
+        let options = JsonLinesOptions {
+            path: Some(".n".to_string()),
+            ..JsonLinesOptions::default()
+        };
+        let sorted = |contents: &[&str]| {
+            let contents: Vec<Vec<u8>> = contents.iter().map(|c| c.as_bytes().to_vec()).collect();
+            let mut output = Vec::new();
+            write_json_lines(&mut output, &contents, &options).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        // 末尾に改行がなければ、出力の末尾にも改行を付けない
+        assert_eq!(
+            sorted(&["{\"n\": 2}\r\n{\"n\": 1}"]),
+            "{\"n\": 1}\n{\"n\": 2}\r"
+        );
+        assert_eq!(
+            sorted(&["{\"n\": 2}\n{\"n\": 1}\n"]),
+            "{\"n\": 1}\n{\"n\": 2}\n"
+        );
+        // 複数のファイルでは、最後のファイルの末尾に合わせる
+        assert_eq!(
+            sorted(&["{\"n\": 3}", "{\"n\": 1}\n", ""]),
+            "{\"n\": 1}\n{\"n\": 3}\n"
+        );
+        assert_eq!(sorted(&[""]), "");
+        // 末尾の空行もそのまま書き出す
+        assert_eq!(
+            sorted(&["{\"n\": 2}\n\n{\"n\": 1}\n\n"]),
+            "{\"n\": 1}\n{\"n\": 2}\n\n\n"
+        );
+    }
+
+    #[test]
+    fn parse_json_lines_flags() {
+        let options = parse_json_lines_args(
+            "-k .request.latency_ms -r --missing=first -o out.jsonl a.jsonl b.jsonl"
+                .split(' ')
+                .map(String::from),
+        )
+        .unwrap();
+        assert_eq!(
+            options,
+            JsonLinesOptions {
+                path: Some(".request.latency_ms".to_string()),
+                order: Descending,
+                missing: MissingKeys::First,
+                output: Some("out.jsonl".to_string()),
+                parallel: None,
+                files: vec!["a.jsonl".to_string(), "b.jsonl".to_string()],
+            }
+        );
+        // 短いオプションは、ソートのときと同じくまとめて書ける
+        let options = parse_json_lines_args(vec!["-rk.a".to_string()].into_iter()).unwrap();
+        assert_eq!(options.path, Some(".a".to_string()));
+        assert_eq!(options.order, Descending);
+        assert!(parse_json_lines_args(vec!["-rx".to_string()].into_iter()).is_err());
+        assert!(parse_json_lines_args(vec!["a.jsonl".to_string()].into_iter()).is_err());
+        assert!(parse_json_lines_args(
+            vec!["-k.a".to_string(), "--missing=middle".to_string()].into_iter()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,281 @@
+// 複数のキーで比較する比較関数を組み立てるビルダー
+// 最初のキーで等しかったら次のキーで比較する、というように先に追加したキーを優先する
+// キーごとに昇順、降順を選べる
+//
+// let comparator = Comparator::by_ref_key(|s: &Student| &s.last_name)
+//     .then_by_ref_key(|s| &s.first_name)
+//     .then_by_key_desc(|s| s.age)
+//     .build();
+// fourth::sort_by(&mut x, &comparator)?;
+//
+// build()が返すクロージャはSyncなので、third::sort_byにもfourth::sort_byにも渡せる
+//
+// by_keyはfourth::sort_by_keyと同じく、キーを値で返すクロージャを受け取る
+// Stringのフィールドのように参照のままキーにしたいときはby_ref_keyを使う
+
+use super::SortOrder;
+use std::cmp::Ordering;
+
+// Option型のキーでNoneを並べる位置
+// 順序(SortOrder)によらず、先頭か末尾のどちらかにまとめる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+type Compare<'a, T> = Box<dyn Fn(&T, &T) -> Ordering + Send + Sync + 'a>;
+
+pub struct Comparator<'a, T> {
+    // 優先順位の高い順に並べた比較関数
+    keys: Vec<Compare<'a, T>>,
+}
+
+impl<'a, T: 'a> Comparator<'a, T> {
+    // キーを持たない比較関数。すべての要素を等しいとみなす
+    pub fn new() -> Self {
+        Comparator { keys: Vec::new() }
+    }
+
+    pub fn by<F>(compare: F) -> Self
+    where
+        F: Fn(&T, &T) -> Ordering + Send + Sync + 'a,
+    {
+        Self::new().then_by(compare)
+    }
+
+    pub fn by_key<K, F>(key: F) -> Self
+    where
+        K: Ord,
+        F: Fn(&T) -> K + Send + Sync + 'a,
+    {
+        Self::new().then_by_key(key)
+    }
+
+    pub fn by_key_desc<K, F>(key: F) -> Self
+    where
+        K: Ord,
+        F: Fn(&T) -> K + Send + Sync + 'a,
+    {
+        Self::new().then_by_key_desc(key)
+    }
+
+    pub fn by_ref_key<K, F>(key: F) -> Self
+    where
+        K: Ord + ?Sized,
+        F: Fn(&T) -> &K + Send + Sync + 'a,
+    {
+        Self::new().then_by_ref_key(key)
+    }
+
+    pub fn by_ref_key_desc<K, F>(key: F) -> Self
+    where
+        K: Ord + ?Sized,
+        F: Fn(&T) -> &K + Send + Sync + 'a,
+    {
+        Self::new().then_by_ref_key_desc(key)
+    }
+
+    pub fn by_option_key<K, F>(key: F, order: SortOrder, nulls: Nulls) -> Self
+    where
+        K: Ord + ?Sized,
+        F: Fn(&T) -> Option<&K> + Send + Sync + 'a,
+    {
+        Self::new().then_by_option_key(key, order, nulls)
+    }
+
+    // ここまでのキーで等しかった要素を、compareで比較する
+    pub fn then_by<F>(mut self, compare: F) -> Self
+    where
+        F: Fn(&T, &T) -> Ordering + Send + Sync + 'a,
+    {
+        self.keys.push(Box::new(compare));
+        self
+    }
+
+    pub fn then_by_key<K, F>(self, key: F) -> Self
+    where
+        K: Ord,
+        F: Fn(&T) -> K + Send + Sync + 'a,
+    {
+        self.then_by(move |a, b| key(a).cmp(&key(b)))
+    }
+
+    pub fn then_by_key_desc<K, F>(self, key: F) -> Self
+    where
+        K: Ord,
+        F: Fn(&T) -> K + Send + Sync + 'a,
+    {
+        self.then_by(move |a, b| key(b).cmp(&key(a)))
+    }
+
+    pub fn then_by_ref_key<K, F>(self, key: F) -> Self
+    where
+        K: Ord + ?Sized,
+        F: Fn(&T) -> &K + Send + Sync + 'a,
+    {
+        self.then_by(move |a, b| key(a).cmp(key(b)))
+    }
+
+    pub fn then_by_ref_key_desc<K, F>(self, key: F) -> Self
+    where
+        K: Ord + ?Sized,
+        F: Fn(&T) -> &K + Send + Sync + 'a,
+    {
+        self.then_by(move |a, b| key(b).cmp(key(a)))
+    }
+
+    // Option型のキーで比較する
+    // Someの値はorderの順に並べ、Noneはnullsの位置にまとめる
+    pub fn then_by_option_key<K, F>(self, key: F, order: SortOrder, nulls: Nulls) -> Self
+    where
+        K: Ord + ?Sized,
+        F: Fn(&T) -> Option<&K> + Send + Sync + 'a,
+    {
+        self.then_by(move |a, b| match (key(a), key(b)) {
+            (Some(x), Some(y)) => match order {
+                SortOrder::Ascending => x.cmp(y),
+                SortOrder::Descending => y.cmp(x),
+            },
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => match nulls {
+                Nulls::First => Ordering::Less,
+                Nulls::Last => Ordering::Greater,
+            },
+            (Some(_), None) => match nulls {
+                Nulls::First => Ordering::Greater,
+                Nulls::Last => Ordering::Less,
+            },
+        })
+    }
+
+    // 先に追加したキーから順に比較し、最初に等しくなかった結果を返す
+    pub fn compare(&self, a: &T, b: &T) -> Ordering {
+        self.keys
+            .iter()
+            .map(|compare| compare(a, b))
+            .find(|&ordering| ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    }
+
+    // sort_byに渡せるクロージャにする
+    pub fn build(self) -> impl Fn(&T, &T) -> Ordering + Send + Sync + 'a {
+        move |a, b| self.compare(a, b)
+    }
+}
+
+impl<'a, T: 'a> Default for Comparator<'a, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Comparator, Nulls};
+    use crate::SortOrder::*;
+    use crate::{fourth, third};
+
+    #[derive(Debug, PartialEq)]
+    struct Record {
+        last_name: String,
+        first_name: String,
+        age: u8,
+        nickname: Option<String>,
+    }
+
+    impl Record {
+        fn new(last_name: &str, first_name: &str, age: u8, nickname: Option<&str>) -> Self {
+            Self {
+                last_name: last_name.to_string(),
+                first_name: first_name.to_string(),
+                age,
+                nickname: nickname.map(|s| s.to_string()),
+            }
+        }
+    }
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record::new("Yamada", "Taro", 16, Some("taro")),
+            Record::new("Ito", "Kyoko", 15, None),
+            Record::new("Yamada", "Hanako", 14, None),
+            Record::new("Yamada", "Taro", 18, Some("tarochan")),
+            Record::new("Hayashi", "Ryosuke", 17, Some("ryo")),
+            Record::new("Yamada", "Taro", 16, None),
+        ]
+    }
+
+    fn names(x: &[Record]) -> Vec<(&str, u8, Option<&str>)> {
+        x.iter()
+            .map(|r| (r.first_name.as_str(), r.age, r.nickname.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn sort_by_several_keys() {
+        let comparator = Comparator::by_ref_key(|r: &Record| &r.last_name)
+            .then_by_ref_key(|r| &r.first_name)
+            .then_by_key_desc(|r| r.age)
+            .then_by_option_key(|r| r.nickname.as_deref(), Ascending, Nulls::Last)
+            .build();
+        let expected = vec![
+            ("Ryosuke", 17, Some("ryo")),
+            ("Kyoko", 15, None),
+            ("Hanako", 14, None),
+            ("Taro", 18, Some("tarochan")),
+            ("Taro", 16, Some("taro")),
+            ("Taro", 16, None),
+        ];
+
+        let mut x = records();
+        assert_eq!(third::sort_by(&mut x, &comparator), Ok(()));
+        assert_eq!(names(&x), expected);
+
+        let mut x = records();
+        assert_eq!(fourth::sort_by(&mut x, &comparator), Ok(()));
+        assert_eq!(names(&x), expected);
+    }
+
+    #[test]
+    fn option_keys_keep_nulls_in_place() {
+        // Noneの位置は降順にしても変わらない
+        let mut x = records();
+        let comparator =
+            Comparator::by_option_key(|r: &Record| r.nickname.as_deref(), Descending, Nulls::First)
+                .then_by_key(|r| r.age)
+                .build();
+        assert_eq!(fourth::sort_by(&mut x, &comparator), Ok(()));
+        assert_eq!(
+            names(&x),
+            vec![
+                ("Hanako", 14, None),
+                ("Kyoko", 15, None),
+                ("Taro", 16, None),
+                ("Taro", 18, Some("tarochan")),
+                ("Taro", 16, Some("taro")),
+                ("Ryosuke", 17, Some("ryo")),
+            ]
+        );
+    }
+
+    #[test]
+    fn compare_with_custom_and_empty_keys() {
+        let a = Record::new("Ito", "Kyoko", 15, None);
+        let b = Record::new("ito", "kyoko", 15, None);
+        // キーがなければ等しい
+        let comparator = Comparator::new();
+        assert_eq!(comparator.compare(&a, &b), std::cmp::Ordering::Equal);
+
+        // 大文字と小文字を区別しない比較と、区別する比較を組み合わせる
+        let comparator = Comparator::by(|a: &Record, b: &Record| {
+            a.last_name.to_lowercase().cmp(&b.last_name.to_lowercase())
+        })
+        .then_by_ref_key_desc(|r| &r.first_name);
+        assert_eq!(comparator.compare(&a, &b), std::cmp::Ordering::Greater);
+        assert_eq!(
+            Comparator::by_key_desc(|r: &Record| r.age).compare(&a, &b),
+            std::cmp::Ordering::Equal
+        );
+    }
+}
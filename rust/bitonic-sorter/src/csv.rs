@@ -0,0 +1,528 @@
+// CSVの読み書きと、型と順序を指定した複数の列によるソート
+// 読み書きはRFC 4180に従う
+// - フィールドはカンマ、レコードは改行(CRLFまたはLF)で区切る
+// - ダブルクォートで囲んだフィールドには、カンマ、改行、""と書いたダブルクォートを含められる
+// - 1行目はヘッダとして扱い、ソートしない
+//
+// let mut table = csv::parse(&input)?;
+// let keys = [
+//     SortKey::new(table.column_index("date").unwrap(), ColumnType::Date, SortOrder::Descending),
+//     SortKey::new(table.column_index("price").unwrap(), ColumnType::Float, SortOrder::Ascending),
+// ];
+// csv::sort_table(&mut table, &keys)?;
+// print!("{}", csv::write(&table));
+
+use super::fourth;
+use super::permutation::permute_in_place;
+use super::{SortError, SortOrder};
+use std::cmp::Ordering;
+use std::{error, fmt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub header: Vec<String>,
+    pub records: Vec<Vec<String>>,
+}
+
+impl Table {
+    // 名前がnameの列の位置
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.header.iter().position(|h| h == name)
+    }
+}
+
+// 列の値の型
+// 空のフィールドはどの型でも値がないものとして扱い、昇順では先頭に並べる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    // i64の整数
+    Integer,
+    // f64の浮動小数点数。NaNは受け付けない
+    Float,
+    // バイト列としての比較
+    String,
+    // YYYY-MM-DD形式の日付
+    Date,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub column: usize,
+    pub column_type: ColumnType,
+    pub order: SortOrder,
+}
+
+impl SortKey {
+    pub fn new(column: usize, column_type: ColumnType, order: SortOrder) -> Self {
+        Self {
+            column,
+            column_type,
+            order,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CsvError {
+    // 入力が空でヘッダがない
+    MissingHeader,
+    // lineは問題のあるレコードが始まる行(1から数える)
+    UnterminatedQuote {
+        line: usize,
+    },
+    UnexpectedQuote {
+        line: usize,
+    },
+    FieldCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    // rowはヘッダを除いて1から数えたレコードの位置
+    InvalidValue {
+        row: usize,
+        column: String,
+        value: String,
+    },
+    ColumnOutOfRange {
+        column: usize,
+    },
+    Sort(SortError),
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CsvError::MissingHeader => write!(f, "The input has no header row."),
+            CsvError::UnterminatedQuote { line } => {
+                write!(f, "A quoted field is not terminated. (line: {})", line)
+            }
+            CsvError::UnexpectedQuote { line } => write!(
+                f,
+                "A quote appears in an unquoted field or after a closing quote. (line: {})",
+                line
+            ),
+            CsvError::FieldCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "The record has {} fields but the header has {}. (line: {})",
+                found, expected, line
+            ),
+            CsvError::InvalidValue { row, column, value } => write!(
+                f,
+                "Invalid value {:?} in column {:?}. (row: {})",
+                value, column, row
+            ),
+            CsvError::ColumnOutOfRange { column } => {
+                write!(f, "The column does not exist. (column: {})", column)
+            }
+            CsvError::Sort(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for CsvError {}
+
+impl From<SortError> for CsvError {
+    fn from(e: SortError) -> Self {
+        CsvError::Sort(e)
+    }
+}
+
+// 入力全体を読み、1行目をヘッダとした表にする
+// すべてのレコードはヘッダと同じ数のフィールドを持たなければならない
+pub fn parse(input: &str) -> Result<Table, CsvError> {
+    let mut records = Vec::new();
+    let mut line = 1;
+    let mut rest = input;
+    while !rest.is_empty() {
+        let start_line = line;
+        let (record, consumed) = parse_record(rest, &mut line)?;
+        rest = &rest[consumed..];
+        records.push((start_line, record));
+    }
+    let mut records = records.into_iter();
+    let header = match records.next() {
+        Some((_, header)) => header,
+        None => return Err(CsvError::MissingHeader),
+    };
+    let records = records
+        .map(|(line, record)| {
+            if record.len() == header.len() {
+                Ok(record)
+            } else {
+                Err(CsvError::FieldCount {
+                    line,
+                    expected: header.len(),
+                    found: record.len(),
+                })
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Table { header, records })
+}
+
+// inputの先頭から1レコードを読み、レコードと読んだバイト数を返す
+// lineは読んだ改行の数だけ進める
+fn parse_record(input: &str, line: &mut usize) -> Result<(Vec<String>, usize), CsvError> {
+    let start_line = *line;
+    let bytes = input.as_bytes();
+    let mut fields = Vec::new();
+    let mut i = 0;
+    loop {
+        let mut field = String::new();
+        if bytes.get(i) == Some(&b'"') {
+            // 引用符で囲まれたフィールド
+            i += 1;
+            loop {
+                let j = match input[i..].find('"') {
+                    Some(j) => i + j,
+                    None => return Err(CsvError::UnterminatedQuote { line: start_line }),
+                };
+                *line += input[i..j].matches('\n').count();
+                field.push_str(&input[i..j]);
+                i = j + 1;
+                if bytes.get(i) == Some(&b'"') {
+                    // ""は1つのダブルクォート
+                    field.push('"');
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            if field_end(bytes, i) != i {
+                return Err(CsvError::UnexpectedQuote { line: *line });
+            }
+        } else {
+            let end = field_end(bytes, i);
+            if input[i..end].contains('"') {
+                return Err(CsvError::UnexpectedQuote { line: *line });
+            }
+            field.push_str(&input[i..end]);
+            i = end;
+        }
+        fields.push(field);
+        match bytes.get(i) {
+            Some(b',') => i += 1,
+            Some(b'\r') => {
+                *line += 1;
+                return Ok((fields, i + 2));
+            }
+            Some(_) => {
+                *line += 1;
+                return Ok((fields, i + 1));
+            }
+            None => return Ok((fields, i)),
+        }
+    }
+}
+
+// i番目から始まる引用符のないフィールドの終わり
+// カンマ、LF、CRLFの位置か、入力の終わりを返す
+// CRLFになっていないCRはフィールドの一部とみなす
+fn field_end(bytes: &[u8], i: usize) -> usize {
+    let mut j = i;
+    while j < bytes.len() {
+        match bytes[j] {
+            b',' | b'\n' => break,
+            b'\r' if bytes.get(j + 1) == Some(&b'\n') => break,
+            _ => j += 1,
+        }
+    }
+    j
+}
+
+// 表をRFC 4180の形式で書き出す
+// カンマ、ダブルクォート、改行を含むフィールドだけを引用符で囲み、レコードはCRLFで区切る
+pub fn write(table: &Table) -> String {
+    let mut output = String::new();
+    for record in Some(&table.header).into_iter().chain(table.records.iter()) {
+        for (i, field) in record.iter().enumerate() {
+            if i > 0 {
+                output.push(',');
+            }
+            if field.contains([',', '"', '\r', '\n']) {
+                output.push('"');
+                output.push_str(&field.replace('"', "\"\""));
+                output.push('"');
+            } else {
+                output.push_str(field);
+            }
+        }
+        output.push_str("\r\n");
+    }
+    output
+}
+
+// keysの順に比べて、最初に差がついた列で順を決める
+// すべての列が等しいレコードは元の順のまま残る
+pub fn sort_table(table: &mut Table, keys: &[SortKey]) -> Result<(), CsvError> {
+    for key in keys.iter() {
+        if key.column >= table.header.len() {
+            return Err(CsvError::ColumnOutOfRange { column: key.column });
+        }
+    }
+    // キーの値は比較のたびに解析しないように、先にまとめて解析しておく
+    // 解析に失敗してもtableを変更しないように、レコードは借用するだけにして、
+    // 並べ替えはすべての行の解析が終わってから行う
+    let records = &table.records;
+    let mut rows = Vec::new();
+    rows.try_reserve_exact(records.len())
+        .map_err(|_| SortError::AllocationFailed)?;
+    for (i, record) in records.iter().enumerate() {
+        let values = keys
+            .iter()
+            .map(|key| {
+                parse_value(&record[key.column], key.column_type).ok_or_else(|| {
+                    CsvError::InvalidValue {
+                        row: i + 1,
+                        column: table.header[key.column].clone(),
+                        value: record[key.column].clone(),
+                    }
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.push((values, i));
+    }
+    fourth::sort_by(&mut rows, &|a, b| {
+        keys.iter()
+            .zip(a.0.iter().zip(b.0.iter()))
+            .map(|(key, (x, y))| {
+                let ordering =
+                    compare_values(x, y, &records[a.1][key.column], &records[b.1][key.column]);
+                match key.order {
+                    SortOrder::Ascending => ordering,
+                    SortOrder::Descending => ordering.reverse(),
+                }
+            })
+            .find(|&ordering| ordering != Ordering::Equal)
+            .unwrap_or_else(|| a.1.cmp(&b.1))
+    })?;
+    let order: Vec<usize> = rows.into_iter().map(|(_, i)| i).collect();
+    permute_in_place(&mut table.records, order);
+    Ok(())
+}
+
+// 解析済みのキーの値
+// 文字列はレコードのフィールドをそのまま比べるので、値を持たない
+// 同じ型の値同士は、導出したPartialOrdで比べられる
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Value {
+    Missing,
+    Integer(i64),
+    Float(f64),
+    String,
+    Date(i32, u32, u32),
+}
+
+fn parse_value(field: &str, column_type: ColumnType) -> Option<Value> {
+    if field.is_empty() {
+        return Some(Value::Missing);
+    }
+    match column_type {
+        ColumnType::Integer => field.trim().parse().ok().map(Value::Integer),
+        ColumnType::Float => match field.trim().parse::<f64>() {
+            Ok(v) if !v.is_nan() => Some(Value::Float(v)),
+            _ => None,
+        },
+        ColumnType::String => Some(Value::String),
+        ColumnType::Date => parse_date(field.trim()),
+    }
+}
+
+// YYYY-MM-DD形式で、実在する日付だけを受け付ける
+fn parse_date(s: &str) -> Option<Value> {
+    let bytes = s.as_bytes();
+    if bytes.len() != 10 || bytes[4] != b'-' || bytes[7] != b'-' {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| -> Option<u32> {
+        let part = &s[range];
+        if part.bytes().all(|b| b.is_ascii_digit()) {
+            part.parse().ok()
+        } else {
+            None
+        }
+    };
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if day == 0 || day > days {
+        return None;
+    }
+    Some(Value::Date(year as i32, month, day))
+}
+
+// 値のない方を先に並べる
+fn compare_values(x: &Value, y: &Value, x_field: &str, y_field: &str) -> Ordering {
+    match (x, y) {
+        (Value::Missing, Value::Missing) => Ordering::Equal,
+        (Value::Missing, _) => Ordering::Less,
+        (_, Value::Missing) => Ordering::Greater,
+        (Value::String, Value::String) => x_field.cmp(y_field),
+        _ => x.partial_cmp(y).unwrap_or(Ordering::Equal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, sort_table, write, ColumnType, CsvError, SortKey, Table};
+    use crate::SortOrder::*;
+
+    fn strings(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_rfc4180_quoting() {
+        let input = "name,note\r\n\"Smith, J\",\"said \"\"hi\"\"\"\r\nLee,\"two\nlines\"\r\nKim,\n";
+        let table = parse(input).unwrap();
+        assert_eq!(table.header, strings(&["name", "note"]));
+        assert_eq!(
+            table.records,
+            vec![
+                strings(&["Smith, J", "said \"hi\""]),
+                strings(&["Lee", "two\nlines"]),
+                strings(&["Kim", ""]),
+            ]
+        );
+        // 書き出して読み直すと同じ表になる
+        let written = write(&table);
+        assert_eq!(
+            written,
+            "name,note\r\n\"Smith, J\",\"said \"\"hi\"\"\"\r\nLee,\"two\nlines\"\r\nKim,\r\n"
+        );
+        assert_eq!(parse(&written), Ok(table));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse(""), Err(CsvError::MissingHeader));
+        assert_eq!(
+            parse("a,b\n1,\"2\n3\n"),
+            Err(CsvError::UnterminatedQuote { line: 2 })
+        );
+        assert_eq!(
+            parse("a,b\n1,\"2\"x\n"),
+            Err(CsvError::UnexpectedQuote { line: 2 })
+        );
+        assert_eq!(
+            parse("a,b\n\"x\ny\",2\n1,2,3\n"),
+            Err(CsvError::FieldCount {
+                line: 4,
+                expected: 2,
+                found: 3
+            })
+        );
+    }
+
+    #[test]
+    fn sort_by_typed_columns() {
+        let mut table = parse(
+            "date,price,qty,item\n\
+             2020-02-29,1.5,10,apple\n\
+             2019-12-31,20,3,pear\n\
+             2020-02-29,1.5,2,fig\n\
+             ,0.25,7,kiwi\n\
+             2020-02-29,-3e2,10,plum\n",
+        )
+        .unwrap();
+        let keys = [
+            SortKey::new(0, ColumnType::Date, Descending),
+            SortKey::new(1, ColumnType::Float, Ascending),
+            SortKey::new(2, ColumnType::Integer, Ascending),
+        ];
+        assert_eq!(sort_table(&mut table, &keys), Ok(()));
+        let items: Vec<&str> = table.records.iter().map(|r| r[3].as_str()).collect();
+        // 日付のないレコードは、降順では最後に並ぶ
+        assert_eq!(items, vec!["plum", "fig", "apple", "pear", "kiwi"]);
+
+        let item = table.column_index("item").unwrap();
+        assert_eq!(
+            sort_table(
+                &mut table,
+                &[SortKey::new(item, ColumnType::String, Ascending)]
+            ),
+            Ok(())
+        );
+        assert_eq!(table.records[0][3], "apple");
+    }
+
+    #[test]
+    fn keep_order_of_equal_records() {
+        let mut table = Table {
+            header: strings(&["k", "v"]),
+            records: (0..100)
+                .map(|i| vec![(i % 3).to_string(), i.to_string()])
+                .collect(),
+        };
+        let keys = [SortKey::new(0, ColumnType::Integer, Ascending)];
+        assert_eq!(sort_table(&mut table, &keys), Ok(()));
+        let values: Vec<i32> = table
+            .records
+            .iter()
+            .map(|r| r[1].parse().unwrap())
+            .collect();
+        let mut expected = values.clone();
+        expected.sort_by_key(|v| (v % 3, *v));
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn reject_invalid_values() {
+        let mut table = parse("d,n\n2021-02-29,1\n").unwrap();
+        assert_eq!(
+            sort_table(&mut table, &[SortKey::new(0, ColumnType::Date, Ascending)]),
+            Err(CsvError::InvalidValue {
+                row: 1,
+                column: "d".to_string(),
+                value: "2021-02-29".to_string(),
+            })
+        );
+        let mut table = parse("d,n\n2021-02-28,NaN\n").unwrap();
+        assert!(sort_table(&mut table, &[SortKey::new(1, ColumnType::Float, Ascending)]).is_err());
+        assert_eq!(
+            sort_table(
+                &mut table,
+                &[SortKey::new(2, ColumnType::Integer, Ascending)]
+            ),
+            Err(CsvError::ColumnOutOfRange { column: 2 })
+        );
+    }
+
+    #[test]
+    fn keep_table_on_invalid_value() {
+        // 途中の行で解析に失敗しても、レコードは失われずに元の順のまま残る
+        let mut table = parse(
+            "n,name
+3,c
+1,a
+x,b
+2,d
+",
+        )
+        .unwrap();
+        let original = table.records.clone();
+        assert_eq!(
+            sort_table(
+                &mut table,
+                &[SortKey::new(0, ColumnType::Integer, Ascending)]
+            ),
+            Err(CsvError::InvalidValue {
+                row: 3,
+                column: "n".to_string(),
+                value: "x".to_string(),
+            })
+        );
+        assert_eq!(table.records, original);
+    }
+}
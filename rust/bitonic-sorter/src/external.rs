@@ -0,0 +1,579 @@
+// 外部ソート
+// メモリに載りきらない大きさのファイルを、予算内に収まるチャンクごとにfourth::sortでソートし、
+// 一時ファイル(ラン)に書き出してから、k-wayマージで1つのファイルにまとめる
+// ファイルは固定長のリトルエンディアンの整数(u32, u64, i64)を隙間なく並べたもの
+//
+// let sorter = ExternalSorter::new(256 * 1024 * 1024).temp_dir("/var/tmp");
+// let count = sorter.sort_file::<u64, _, _, _>(
+//     "input.bin",
+//     "output.bin",
+//     &SortOrder::Ascending,
+//     |progress| eprintln!("{:?}", progress),
+// )?;
+
+use super::fourth;
+use super::{SortError, SortOrder};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::{error, fmt};
+
+// マージで1つのランに割り当てる読み込みバッファの最小の大きさ
+// 予算をこれで割った数が、1回のマージで同時に開くランの数になる
+const MIN_MERGE_BUFFER: usize = 64 * 1024;
+
+// 同時に開くファイルの数の上限
+const MAX_FAN_IN: usize = 512;
+
+// マージの進み具合を報告する間隔(要素数)
+const PROGRESS_INTERVAL: u64 = 1 << 20;
+
+// 一時ファイルの名前が重ならないようにするための通し番号
+static NEXT_RUN_ID: AtomicUsize = AtomicUsize::new(0);
+
+// ファイルに書かれた1要素
+// SIZEは8バイト以下でなければならない
+pub trait Element: Ord + Copy + Send {
+    const SIZE: usize;
+    fn from_le_slice(bytes: &[u8]) -> Self;
+    fn write_le(self, bytes: &mut [u8]);
+}
+
+macro_rules! impl_element {
+    ($($t:ty),*) => {
+        $(
+            impl Element for $t {
+                const SIZE: usize = std::mem::size_of::<$t>();
+
+                fn from_le_slice(bytes: &[u8]) -> Self {
+                    let mut array = [0; std::mem::size_of::<$t>()];
+                    array.copy_from_slice(bytes);
+                    <$t>::from_le_bytes(array)
+                }
+
+                fn write_le(self, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_element!(u32, u64, i64);
+
+#[derive(Debug)]
+pub enum ExternalSortError {
+    Io(io::Error),
+    Sort(SortError),
+    // 予算が1要素分にも満たない
+    BudgetTooSmall { budget: usize },
+    // ファイルの長さが要素の大きさの倍数になっていない
+    InvalidLength { len: u64 },
+}
+
+impl fmt::Display for ExternalSortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExternalSortError::Io(e) => write!(f, "I/O error: {}", e),
+            ExternalSortError::Sort(e) => write!(f, "{}", e),
+            ExternalSortError::BudgetTooSmall { budget } => write!(
+                f,
+                "The memory budget is smaller than one element. (budget: {})",
+                budget
+            ),
+            ExternalSortError::InvalidLength { len } => write!(
+                f,
+                "The file length is not a multiple of the element size. (len: {})",
+                len
+            ),
+        }
+    }
+}
+
+impl error::Error for ExternalSortError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ExternalSortError::Io(e) => Some(e),
+            ExternalSortError::Sort(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ExternalSortError {
+    fn from(e: io::Error) -> Self {
+        ExternalSortError::Io(e)
+    }
+}
+
+impl From<SortError> for ExternalSortError {
+    fn from(e: SortError) -> Self {
+        ExternalSortError::Sort(e)
+    }
+}
+
+// sort_fileが呼び出すコールバックに渡す進み具合
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    // runs個目のランを書き出した。入力のうちelements個を読み終えた
+    RunWritten {
+        runs: usize,
+        elements: u64,
+        total: u64,
+    },
+    // pass回目のマージでmerged個の要素を書き出した
+    // 最後のpassは出力ファイルへのマージになる
+    Merging {
+        pass: usize,
+        merged: u64,
+        total: u64,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct ExternalSorter {
+    memory_budget: usize,
+    temp_dir: PathBuf,
+}
+
+impl ExternalSorter {
+    // memory_budgetはチャンクのソートとマージのバッファに使うバイト数
+    // 一時ファイルは既定ではstd::env::temp_dir()に作る
+    pub fn new(memory_budget: usize) -> Self {
+        Self {
+            memory_budget,
+            temp_dir: std::env::temp_dir(),
+        }
+    }
+
+    // 一時ファイルを作るディレクトリを設定する
+    // 入力と同じ大きさの空き容量が必要になる
+    pub fn temp_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.temp_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    // inputをorderの順にソートしてoutputに書き出し、要素数を返す
+    // 途中でエラーになっても、一時ファイルは削除してから返る
+    pub fn sort_file<T, P, Q, R>(
+        &self,
+        input: P,
+        output: Q,
+        order: &SortOrder,
+        mut progress: R,
+    ) -> Result<u64, ExternalSortError>
+    where
+        T: Element,
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        R: FnMut(Progress),
+    {
+        if self.memory_budget < T::SIZE {
+            return Err(ExternalSortError::BudgetTooSmall {
+                budget: self.memory_budget,
+            });
+        }
+        let len = fs::metadata(&input)?.len();
+        if len % T::SIZE as u64 != 0 {
+            return Err(ExternalSortError::InvalidLength { len });
+        }
+        let total = len / T::SIZE as u64;
+        // runsがドロップされるときに、残っている一時ファイルを削除する
+        let mut runs = RunFiles::new(&self.temp_dir);
+        let mut pending =
+            self.write_runs::<T, _>(&input, total, order, &mut runs, &mut progress)?;
+
+        // ランが多すぎるときは、fan_in個ずつマージしてランの数を減らす
+        let fan_in = (self.memory_budget / MIN_MERGE_BUFFER).clamp(2, MAX_FAN_IN);
+        let mut pass = 1;
+        while pending.len() > fan_in {
+            let mut merged = 0;
+            let mut next = Vec::new();
+            for group in pending.chunks(fan_in) {
+                if group.len() == 1 {
+                    next.push(group[0].clone());
+                    continue;
+                }
+                let path = runs.create()?;
+                merged += self.merge_runs::<T>(group, &path, order, |n| {
+                    progress(Progress::Merging {
+                        pass,
+                        merged: merged + n,
+                        total,
+                    })
+                })?;
+                for run in group.iter() {
+                    runs.remove(run)?;
+                }
+                next.push(path);
+            }
+            pending = next;
+            pass += 1;
+        }
+        self.merge_runs::<T>(&pending, output.as_ref(), order, |merged| {
+            progress(Progress::Merging {
+                pass,
+                merged,
+                total,
+            })
+        })?;
+        Ok(total)
+    }
+
+    // 予算に収まる要素数ずつ読み込んでソートし、ランとして書き出す
+    fn write_runs<T, P>(
+        &self,
+        input: P,
+        total: u64,
+        order: &SortOrder,
+        runs: &mut RunFiles,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<Vec<PathBuf>, ExternalSortError>
+    where
+        T: Element,
+        P: AsRef<Path>,
+    {
+        let chunk_len = (self.memory_budget / T::SIZE) as u64;
+        let mut chunk: Vec<T> = Vec::new();
+        chunk
+            .try_reserve_exact(chunk_len.min(total) as usize)
+            .map_err(|_| SortError::AllocationFailed)?;
+        let mut reader = BufReader::new(File::open(input)?);
+        let mut paths = Vec::new();
+        let mut read = 0;
+        while read < total {
+            let n = chunk_len.min(total - read);
+            chunk.clear();
+            for _ in 0..n {
+                match read_element(&mut reader)? {
+                    Some(value) => chunk.push(value),
+                    // 読み込み中にファイルが短くなった
+                    None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                }
+            }
+            fourth::sort(&mut chunk, order)?;
+            let path = runs.create()?;
+            let mut writer = BufWriter::new(File::create(&path)?);
+            for &value in chunk.iter() {
+                write_element(&mut writer, value)?;
+            }
+            writer.flush()?;
+            paths.push(path);
+            read += n;
+            progress(Progress::RunWritten {
+                runs: paths.len(),
+                elements: read,
+                total,
+            });
+        }
+        Ok(paths)
+    }
+
+    // ソート済みのランをヒープでマージしてoutputに書き出し、要素数を返す
+    // 予算はランと出力のバッファで等分する
+    fn merge_runs<T: Element>(
+        &self,
+        runs: &[PathBuf],
+        output: &Path,
+        order: &SortOrder,
+        mut progress: impl FnMut(u64),
+    ) -> Result<u64, ExternalSortError> {
+        let buffer_len = (self.memory_budget / (runs.len() + 1)).max(T::SIZE);
+        let mut readers = Vec::with_capacity(runs.len());
+        for path in runs.iter() {
+            readers.push(BufReader::with_capacity(buffer_len, File::open(path)?));
+        }
+        let mut writer = BufWriter::with_capacity(buffer_len, File::create(output)?);
+        let mut heap: BinaryHeap<HeapEntry<T>> = BinaryHeap::with_capacity(readers.len());
+        for (run, reader) in readers.iter_mut().enumerate() {
+            if let Some(value) = read_element(reader)? {
+                heap.push(HeapEntry {
+                    value,
+                    run,
+                    order: *order,
+                });
+            }
+        }
+        let mut merged = 0;
+        while let Some(entry) = heap.pop() {
+            write_element(&mut writer, entry.value)?;
+            merged += 1;
+            if merged % PROGRESS_INTERVAL == 0 {
+                progress(merged);
+            }
+            if let Some(value) = read_element(&mut readers[entry.run])? {
+                heap.push(HeapEntry { value, ..entry });
+            }
+        }
+        writer.flush()?;
+        if merged % PROGRESS_INTERVAL != 0 || merged == 0 {
+            progress(merged);
+        }
+        Ok(merged)
+    }
+}
+
+// BinaryHeapは最大の要素を取り出すので、先に並ぶべき要素ほど大きいとみなす
+// 値が等しいときは番号の小さいランを先にする
+struct HeapEntry<T> {
+    value: T,
+    run: usize,
+    order: SortOrder,
+}
+
+impl<T: Ord> Ord for HeapEntry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let by_value = match self.order {
+            SortOrder::Ascending => other.value.cmp(&self.value),
+            SortOrder::Descending => self.value.cmp(&other.value),
+        };
+        by_value.then_with(|| other.run.cmp(&self.run))
+    }
+}
+
+impl<T: Ord> PartialOrd for HeapEntry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Ord> PartialEq for HeapEntry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: Ord> Eq for HeapEntry<T> {}
+
+// 作成した一時ファイルを覚えておき、ドロップされるときにすべて削除する
+// エラーで途中から戻っても、?で抜けるときにドロップされるので一時ファイルは残らない
+struct RunFiles {
+    dir: PathBuf,
+    paths: Vec<PathBuf>,
+}
+
+impl RunFiles {
+    fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            paths: Vec::new(),
+        }
+    }
+
+    // 他のプロセスやソートと重ならない名前で、空の一時ファイルを作る
+    fn create(&mut self) -> io::Result<PathBuf> {
+        let id = NEXT_RUN_ID.fetch_add(1, AtomicOrdering::Relaxed);
+        let path = self
+            .dir
+            .join(format!("bitonic-sorter-{}-{}.run", process::id(), id));
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        self.paths.push(path.clone());
+        Ok(path)
+    }
+
+    fn remove(&mut self, path: &Path) -> io::Result<()> {
+        self.paths.retain(|p| p != path);
+        fs::remove_file(path)
+    }
+}
+
+impl Drop for RunFiles {
+    fn drop(&mut self) {
+        for path in self.paths.iter() {
+            // 削除に失敗しても、ドロップ中にできることはない
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// 1要素を読み込む。ファイルの終わりならNoneを返す
+fn read_element<T: Element, R: Read>(reader: &mut R) -> io::Result<Option<T>> {
+    let mut array = [0; 8];
+    let bytes = &mut array[..T::SIZE];
+    let mut filled = 0;
+    while filled < bytes.len() {
+        match reader.read(&mut bytes[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    match filled {
+        0 => Ok(None),
+        n if n == bytes.len() => Ok(Some(T::from_le_slice(bytes))),
+        _ => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+    }
+}
+
+fn write_element<T: Element, W: Write>(writer: &mut W, value: T) -> io::Result<()> {
+    let mut array = [0; 8];
+    let bytes = &mut array[..T::SIZE];
+    value.write_le(bytes);
+    writer.write_all(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Element, ExternalSortError, ExternalSorter, Progress};
+    use crate::utils::new_u32_vec;
+    use crate::SortOrder::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // テストごとに別のディレクトリを使い、一時ファイルが残っていないか確かめられるようにする
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "bitonic-sorter-external-{}-{}",
+            std::process::id(),
+            name
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_file<T: Element>(path: &PathBuf, values: &[T]) {
+        let mut bytes = vec![0; values.len() * T::SIZE];
+        for (chunk, &value) in bytes.chunks_mut(T::SIZE).zip(values.iter()) {
+            value.write_le(chunk);
+        }
+        fs::write(path, bytes).unwrap();
+    }
+
+    fn read_file<T: Element>(path: &PathBuf) -> Vec<T> {
+        fs::read(path)
+            .unwrap()
+            .chunks(T::SIZE)
+            .map(T::from_le_slice)
+            .collect()
+    }
+
+    #[test]
+    fn sort_u32_file_with_many_runs() {
+        let dir = test_dir("u32");
+        let (input, output) = (dir.join("input.bin"), dir.join("output.bin"));
+        let values = new_u32_vec(10_000);
+        write_file(&input, &values);
+
+        // 予算256バイトでは64要素ずつ、157個のランになる
+        // 同時に2つずつしかマージできないので、157, 79, 40, 20, 10, 5, 3, 2個と
+        // ランを減らしてから、8回目のマージで出力する
+        let sorter = ExternalSorter::new(256).temp_dir(dir.join("tmp"));
+        fs::create_dir_all(dir.join("tmp")).unwrap();
+        let mut events = Vec::new();
+        let count = sorter
+            .sort_file::<u32, _, _, _>(&input, &output, &Ascending, |p| events.push(p))
+            .unwrap();
+        assert_eq!(count, 10_000);
+
+        let mut expected = values;
+        expected.sort();
+        assert_eq!(read_file::<u32>(&output), expected);
+
+        let runs = events
+            .iter()
+            .filter(|p| matches!(p, Progress::RunWritten { .. }))
+            .count();
+        assert_eq!(runs, 157);
+        match events.last() {
+            Some(&Progress::Merging {
+                pass,
+                merged,
+                total,
+            }) => {
+                assert_eq!((pass, merged, total), (8, 10_000, 10_000));
+            }
+            other => panic!("unexpected progress: {:?}", other),
+        }
+        // 一時ファイルはすべて削除されている
+        assert_eq!(fs::read_dir(dir.join("tmp")).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sort_i64_and_u64_descending() {
+        let dir = test_dir("i64");
+        let (input, output) = (dir.join("input.bin"), dir.join("output.bin"));
+        let values: Vec<i64> = new_u32_vec(3000)
+            .into_iter()
+            .map(|v| (i64::from(v) - (1 << 31)) * 12345)
+            .collect();
+        write_file(&input, &values);
+        let sorter = ExternalSorter::new(4096).temp_dir(&dir);
+        sorter
+            .sort_file::<i64, _, _, _>(&input, &output, &Descending, |_| {})
+            .unwrap();
+        let mut expected = values;
+        expected.sort_by(|a, b| b.cmp(a));
+        assert_eq!(read_file::<i64>(&output), expected);
+
+        // 1つのランに収まる場合と、空のファイル
+        for &len in [100, 0].iter() {
+            let values: Vec<u64> = new_u32_vec(len)
+                .into_iter()
+                .map(|v| u64::from(v) << 32)
+                .collect();
+            write_file(&input, &values);
+            let sorter = ExternalSorter::new(1 << 20).temp_dir(&dir);
+            let count = sorter
+                .sort_file::<u64, _, _, _>(&input, &output, &Descending, |_| {})
+                .unwrap();
+            assert_eq!(count, len as u64);
+            let mut expected = values;
+            expected.sort_by(|a, b| b.cmp(a));
+            assert_eq!(read_file::<u64>(&output), expected);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn remove_temporary_files_on_error() {
+        let dir = test_dir("error");
+        let input = dir.join("input.bin");
+        write_file(&input, &new_u32_vec(1000));
+        let tmp = dir.join("tmp");
+        fs::create_dir_all(&tmp).unwrap();
+
+        // ランを書き出した後、出力ファイルを作るときに失敗する
+        let output = dir.join("missing").join("output.bin");
+        let sorter = ExternalSorter::new(400).temp_dir(&tmp);
+        let mut runs = 0;
+        let result = sorter.sort_file::<u32, _, _, _>(&input, &output, &Ascending, |p| {
+            if let Progress::RunWritten { .. } = p {
+                runs += 1;
+            }
+        });
+        match result {
+            Err(ExternalSortError::Io(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(runs, 10);
+        assert_eq!(fs::read_dir(&tmp).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reject_invalid_input() {
+        let dir = test_dir("invalid");
+        let (input, output) = (dir.join("input.bin"), dir.join("output.bin"));
+        fs::write(&input, [0u8; 10]).unwrap();
+        let sorter = ExternalSorter::new(1024).temp_dir(&dir);
+        match sorter.sort_file::<u64, _, _, _>(&input, &output, &Ascending, |_| {}) {
+            Err(ExternalSortError::InvalidLength { len: 10 }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let sorter = ExternalSorter::new(4).temp_dir(&dir);
+        match sorter.sort_file::<u64, _, _, _>(&input, &output, &Ascending, |_| {}) {
+            Err(ExternalSortError::BudgetTooSmall { budget: 4 }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+// f32とf64のソート
+// 浮動小数点数はNaNがあるためOrdを実装しておらず、third::sortやfourth::sortでは扱えない
+// そこで値をIEEE 754の全順序(totalOrder)と同じ順に並ぶ整数のキーに変換し、
+// キーの整数をソートしてから値に戻す。比較はすべて整数の比較になる
+//
+// 全順序では -inf < ... < -0.0 < +0.0 < ... < +inf となり、
+// -0.0は+0.0より前(降順なら後)に並ぶ
+// NaNはNanPolicyに従って、順序によらず先頭か末尾に元の順のまままとめる
+
+use super::{fourth, third, SortError, SortOrder};
+
+// NaNの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NanPolicy {
+    // 先頭にまとめる
+    First,
+    // 末尾にまとめる
+    Last,
+    // SortError::NaNを返し、スライスは変更しない
+    Error,
+}
+
+// sort_floatsでソートできる浮動小数点数型
+pub trait Float: Copy {
+    // 値と同じ順に並ぶ整数のキー
+    type Key: Ord + Copy + Send;
+    fn is_nan(self) -> bool;
+    fn to_key(self) -> Self::Key;
+    fn from_key(key: Self::Key) -> Self;
+}
+
+macro_rules! impl_float {
+    ($($t:ty => $key:ty),*) => {
+        $(
+            impl Float for $t {
+                type Key = $key;
+
+                fn is_nan(self) -> bool {
+                    <$t>::is_nan(self)
+                }
+
+                // 負の数はビットをすべて反転し、正の数は符号ビットだけを立てる
+                // 例(f32): -0.0 = 0x8000_0000 -> 0x7fff_ffff, +0.0 = 0x0000_0000 -> 0x8000_0000
+                fn to_key(self) -> Self::Key {
+                    const SIGN: $key = 1 << (<$key>::BITS - 1);
+                    let bits = self.to_bits();
+                    if bits & SIGN != 0 {
+                        !bits
+                    } else {
+                        bits | SIGN
+                    }
+                }
+
+                fn from_key(key: Self::Key) -> Self {
+                    const SIGN: $key = 1 << (<$key>::BITS - 1);
+                    <$t>::from_bits(if key & SIGN != 0 { key ^ SIGN } else { !key })
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f32 => u32, f64 => u64);
+
+pub fn sort_floats<T: Float>(
+    x: &mut [T],
+    order: &SortOrder,
+    nan_policy: NanPolicy,
+) -> Result<(), SortError> {
+    do_sort(x, order, nan_policy, &third::sort)
+}
+
+// キーの整数をfourth::sortで並列にソートする
+pub fn par_sort_floats<T: Float>(
+    x: &mut [T],
+    order: &SortOrder,
+    nan_policy: NanPolicy,
+) -> Result<(), SortError> {
+    do_sort(x, order, nan_policy, &fourth::sort)
+}
+
+fn do_sort<T, S>(
+    x: &mut [T],
+    order: &SortOrder,
+    nan_policy: NanPolicy,
+    sort: &S,
+) -> Result<(), SortError>
+where
+    T: Float,
+    S: Fn(&mut [T::Key], &SortOrder) -> Result<(), SortError>,
+{
+    if nan_policy == NanPolicy::Error {
+        if let Some(index) = x.iter().position(|v| v.is_nan()) {
+            return Err(SortError::NaN { index });
+        }
+    }
+
+    // NaN以外の値をキーに変換し、NaNは元の順のまま取り分けておく
+    let mut keys = Vec::new();
+    keys.try_reserve_exact(x.len())
+        .map_err(|_| SortError::AllocationFailed)?;
+    let mut nans = Vec::new();
+    for &v in x.iter() {
+        if v.is_nan() {
+            nans.push(v);
+        } else {
+            keys.push(v.to_key());
+        }
+    }
+    sort(&mut keys, order)?;
+
+    let (nan_slots, value_slots) = match nan_policy {
+        NanPolicy::First => x.split_at_mut(nans.len()),
+        _ => {
+            let (values, nan_slots) = x.split_at_mut(keys.len());
+            (nan_slots, values)
+        }
+    };
+    nan_slots.copy_from_slice(&nans);
+    for (slot, &key) in value_slots.iter_mut().zip(keys.iter()) {
+        *slot = T::from_key(key);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{par_sort_floats, sort_floats, Float, NanPolicy};
+    use crate::utils::{new_vec, Distribution};
+    use crate::SortError;
+    use crate::SortOrder::*;
+
+    // NaNや-0.0も区別して比べられるように、ビット列にする
+    fn bits(x: &[f64]) -> Vec<u64> {
+        x.iter().map(|v| v.to_bits()).collect()
+    }
+
+    #[test]
+    fn sort_with_nans_first_and_last() {
+        let nan = f64::NAN;
+        let negative_nan = -f64::NAN;
+        let input = [
+            3.5,
+            nan,
+            -0.0,
+            f64::INFINITY,
+            0.0,
+            -2.0,
+            negative_nan,
+            f64::NEG_INFINITY,
+            0.0,
+        ];
+
+        let mut x = input;
+        assert_eq!(sort_floats(&mut x, &Ascending, NanPolicy::Last), Ok(()));
+        assert_eq!(
+            bits(&x),
+            bits(&[
+                f64::NEG_INFINITY,
+                -2.0,
+                -0.0,
+                0.0,
+                0.0,
+                3.5,
+                f64::INFINITY,
+                nan,
+                negative_nan,
+            ])
+        );
+
+        let mut x = input;
+        assert_eq!(
+            par_sort_floats(&mut x, &Descending, NanPolicy::First),
+            Ok(())
+        );
+        assert_eq!(
+            bits(&x),
+            bits(&[
+                nan,
+                negative_nan,
+                f64::INFINITY,
+                3.5,
+                0.0,
+                0.0,
+                -0.0,
+                -2.0,
+                f64::NEG_INFINITY,
+            ])
+        );
+    }
+
+    #[test]
+    fn sort_with_nan_error() {
+        let mut x = [1.0f32, 0.5, f32::NAN, 2.0, f32::NAN];
+        assert_eq!(
+            sort_floats(&mut x, &Ascending, NanPolicy::Error),
+            Err(SortError::NaN { index: 2 })
+        );
+        assert_eq!(x[..2], [1.0, 0.5]);
+
+        let mut x = [1.0f32, -0.0, 0.5, 0.0];
+        assert_eq!(
+            par_sort_floats(&mut x, &Ascending, NanPolicy::Error),
+            Ok(())
+        );
+        assert_eq!(
+            x.iter().map(|v| v.to_bits()).collect::<Vec<_>>(),
+            [-0.0f32, 0.0, 0.5, 1.0]
+                .iter()
+                .map(|v| v.to_bits())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn sort_matches_total_cmp() {
+        // ランダムなビット列から、NaNや非正規化数を含む値を作る
+        let raw: Vec<u64> = new_vec(10_000, Distribution::Uniform, 23);
+        let input: Vec<f64> = raw.iter().map(|&b| f64::from_bits(b)).collect();
+        let mut expected: Vec<f64> = input.iter().cloned().filter(|v| !v.is_nan()).collect();
+        expected.sort_by(|a, b| a.total_cmp(b));
+        let nans: Vec<f64> = input.iter().cloned().filter(|v| v.is_nan()).collect();
+        assert!(!nans.is_empty());
+        expected.extend(nans);
+
+        let mut x = input.clone();
+        assert_eq!(sort_floats(&mut x, &Ascending, NanPolicy::Last), Ok(()));
+        assert_eq!(bits(&x), bits(&expected));
+        let mut x = input;
+        assert_eq!(par_sort_floats(&mut x, &Ascending, NanPolicy::Last), Ok(()));
+        assert_eq!(bits(&x), bits(&expected));
+
+        for &v in [f32::MIN_POSITIVE, -1.5, 0.0, -0.0, f32::NAN, f32::MAX].iter() {
+            assert_eq!(f32::from_key(v.to_key()).to_bits(), v.to_bits());
+        }
+    }
+}
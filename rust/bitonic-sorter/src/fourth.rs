@@ -8,123 +8,650 @@
 
 // Rustでは関数、変数、定数にスネークケース、
 // ユーザが定義した型やジェネリクス型パラメータの識別子にはキャメルケースを用いる
-use super::SortOrder;
+use super::permutation::{identity, permute_in_place, SortIndex};
+use super::sorter::{BaseCase, Sorter};
+use super::{power_of_two_below, SortError, SortOrder};
 use rayon;
+use rayon::prelude::*;
 use std::cmp::Ordering;
 
-// match式による場合分けをしてdo_sort()に渡す
-pub fn sort<T: Ord + Send>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
-    // do_sort()を呼ぶ代わりに、sort_by()を呼ぶようにする
-
-    match *order {
-        // 昇順ならa.cmp(b), 降順ならb.cmp(a)を行う
-        SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
-        SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
-    }
+// 既定の設定のSorterでソートする
+// 閾値などを変えたいときはSorterを直接使う
+pub fn sort<T: Ord + Send>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    Sorter::new().sort(x, order)
 }
 
 // 第2引数comparatorはクロージャを受け取る
 // クロージャの型はジェネリクスになっていて、型パラメータFで示している
 // where節以降にはFnで始まるトレイト境界が指定されている
-pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), String>
+pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
 where
     T: Send,
     F: Sync + Fn(&T, &T) -> Ordering,
 {
-    if x.len().is_power_of_two() {
-        do_sort(x, true, comparator);
-        Ok(())
-    } else {
-        Err(format!(
-            "The length of x is not a power of two. (x.len(): {})",
-            x.len()
-        ))
+    Sorter::new().sort_by(x, comparator)
+}
+
+// 要素から取り出したキーで比較する
+// キーは比較のたびに計算されるので、計算が軽いときに使う
+pub fn sort_by_key<T, K, F>(x: &mut [T], key: &F) -> Result<(), SortError>
+where
+    T: Send,
+    K: Ord,
+    F: Sync + Fn(&T) -> K,
+{
+    Sorter::new().sort_by_key(x, key)
+}
+
+// キーを要素ごとに1回だけ並列に計算し、(キー, 元の位置)の組をソートしてからxを並べ替える
+// 元の位置でも比較するので、キーが等しい要素は元の順のまま残る
+pub fn sort_by_cached_key<T, K, F>(x: &mut [T], key: &F) -> Result<(), SortError>
+where
+    T: Send + Sync,
+    K: Ord + Send,
+    F: Sync + Fn(&T) -> K,
+{
+    let mut keys = Vec::new();
+    keys.try_reserve_exact(x.len())
+        .map_err(|_| SortError::AllocationFailed)?;
+    keys.par_extend(x.par_iter().enumerate().map(|(i, v)| (key(v), i)));
+    sort_by(&mut keys, &|a, b| a.cmp(b))?;
+    permute_in_place(x, keys.into_iter().map(|(_, i)| i).collect());
+    Ok(())
+}
+
+// xは変更せずに、xをソートする並べ替え(置換)を返す
+// 戻り値のp番目は、ソート後にp番目に来る要素のxでの位置
+// 戻り値の型はlet order: Vec<u32> = argsort(...)?;のように指定する
+// 要素数が少ないならu32にするとメモリを節約できる
+pub fn argsort<I, T>(x: &[T], order: &SortOrder) -> Result<Vec<I>, SortError>
+where
+    I: SortIndex,
+    T: Ord + Sync,
+{
+    match *order {
+        SortOrder::Ascending => argsort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => argsort_by(x, &|a, b| b.cmp(a)),
+    }
+}
+
+// 添字の並べ替えはsort_byと同じく並列に行う
+// 比較結果が等しい要素は位置の順に並ぶ
+pub fn argsort_by<I, T, F>(x: &[T], comparator: &F) -> Result<Vec<I>, SortError>
+where
+    I: SortIndex,
+    T: Sync,
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    let mut indices = identity(x.len())?;
+    sort_by(&mut indices, &|a: &I, b: &I| {
+        comparator(&x[a.to_usize()], &x[b.to_usize()]).then_with(|| a.cmp(b))
+    })?;
+    Ok(indices)
+}
+
+// 安定ソート
+// comparatorで等しい要素は、元の順を保ったまま並ぶ
+// バイトニックソートは安定ではないので、argsort_byと同じく元の位置でも比較して
+// 並べ替えを求めてから、xに適用する
+pub fn stable_sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+where
+    T: Sync,
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    let indices: Vec<usize> = argsort_by(x, comparator)?;
+    permute_in_place(x, indices);
+    Ok(())
+}
+
+// ソート済みの2つの列aとbを、ソート済みの1つのVecにまとめる
+// aとbはどちらもorderの順にソートされていなければならない
+// 長さは異なっていてもよい
+pub fn merge_sorted<T>(a: &[T], b: &[T], order: &SortOrder) -> Result<Vec<T>, SortError>
+where
+    T: Ord + Clone + Send,
+{
+    match *order {
+        SortOrder::Ascending => merge_sorted_by(a, b, &|a, b| a.cmp(b)),
+        SortOrder::Descending => merge_sorted_by(a, b, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn merge_sorted_by<T, F>(a: &[T], b: &[T], comparator: &F) -> Result<Vec<T>, SortError>
+where
+    T: Clone + Send,
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    let mut merged = Vec::new();
+    merged
+        .try_reserve_exact(a.len() + b.len())
+        .map_err(|_| SortError::AllocationFailed)?;
+    merged.extend_from_slice(a);
+    merged.extend_from_slice(b);
+    merge_sorted_halves_by(&mut merged, a.len(), comparator)?;
+    Ok(merged)
+}
+
+// x[..mid]とx[mid..]がどちらもorderの順にソート済みのとき、x全体をその場でソートする
+// midがx.len()より大きいときはパニックする
+pub fn merge_sorted_halves<T: Ord + Send>(
+    x: &mut [T],
+    mid: usize,
+    order: &SortOrder,
+) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => merge_sorted_halves_by(x, mid, &|a, b| a.cmp(b)),
+        SortOrder::Descending => merge_sorted_halves_by(x, mid, &|a, b| b.cmp(a)),
+    }
+}
+
+// 並列に処理する閾値は既定の設定のSorterと同じ値を使う
+pub fn merge_sorted_halves_by<T, F>(
+    x: &mut [T],
+    mid: usize,
+    comparator: &F,
+) -> Result<(), SortError>
+where
+    T: Send,
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    let threshold = Sorter::new().resolve::<T>().get_parallel_threshold();
+    let (first, second) = Columns::keys(x).split_at(mid);
+    merge(first, second, true, comparator, threshold);
+    Ok(())
+}
+
+// 先に並ぶk個の要素をorderの順にソートしてx[..k]に集める
+// x[k..]の並びは問わない。kがx.len()以上ならx全体をソートする
+pub fn partial_sort<T: Ord + Send>(
+    x: &mut [T],
+    k: usize,
+    order: &SortOrder,
+) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => partial_sort_by(x, k, &|a, b| a.cmp(b)),
+        SortOrder::Descending => partial_sort_by(x, k, &|a, b| b.cmp(a)),
+    }
+}
+
+// k個ずつのブロックを並列にソートし、2つずつ組にしてバイトニックマージの前半で
+// 先に並ぶk個を選ぶ処理を、トーナメントのように繰り返す
+pub fn partial_sort_by<T, F>(x: &mut [T], k: usize, comparator: &F) -> Result<(), SortError>
+where
+    T: Send,
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    if k > 0 {
+        partial(
+            Columns::keys(x),
+            k,
+            comparator,
+            &Sorter::new().resolve::<T>(),
+        );
+    }
+    Ok(())
+}
+
+// iterの要素のうち、orderの順で先に並ぶk個をソートして返す
+// Descendingなら大きい方からk個になる
+pub fn top_k<I>(iter: I, k: usize, order: &SortOrder) -> Result<Vec<I::Item>, SortError>
+where
+    I: IntoIterator,
+    I::Item: Ord + Send,
+{
+    match *order {
+        SortOrder::Ascending => top_k_by(iter, k, &|a, b| a.cmp(b)),
+        SortOrder::Descending => top_k_by(iter, k, &|a, b| b.cmp(a)),
     }
 }
 
+// iterの読み込みは順番に行うしかないので、並列に処理する閾値以上の要素をまとめて読み込み、
+// そこから先に並ぶk個を並列に選んでから暫定の上位k個とマージする
+pub fn top_k_by<I, F>(iter: I, k: usize, comparator: &F) -> Result<Vec<I::Item>, SortError>
+where
+    I: IntoIterator,
+    I::Item: Send,
+    F: Sync + Fn(&I::Item, &I::Item) -> Ordering,
+{
+    let config = Sorter::new().resolve::<I::Item>();
+    let threshold = config.get_parallel_threshold();
+    let batch_len = k.max(threshold).max(1);
+    let mut iter = iter.into_iter();
+    let mut buffer: Vec<I::Item> = Vec::new();
+    if k == 0 {
+        return Ok(buffer);
+    }
+    buffer.extend(iter.by_ref().take(batch_len));
+    partial(Columns::keys(&mut buffer), k, comparator, &config);
+    buffer.truncate(k);
+    loop {
+        buffer.extend(iter.by_ref().take(batch_len));
+        if buffer.len() <= k {
+            break;
+        }
+        let (front, batch) = buffer.split_at_mut(k);
+        let mut batch = Columns::keys(batch);
+        partial(batch.reborrow(), k, comparator, &config);
+        let batch_len = batch.len().min(k);
+        keep_front(
+            Columns::keys(front),
+            batch.split_at(batch_len).0,
+            true,
+            comparator,
+            threshold,
+        );
+        buffer.truncate(k);
+    }
+    Ok(buffer)
+}
+
 // u32型のみに対応している
 // pub fn sort(x: &mut [u32], up: bool) {
 // 型パラメータTを導入して、関数をジェネリクス化する
 // 全順序だけを受け取るように、型パラメータTのトレイト境界としてOrdを設定
 
-const PARALLEL_THRESHOLD: usize = 4096;
-
-fn do_sort<T, F>(x: &mut [T], forward: bool, comparator: &F)
+// ネットワークの形はthird::do_sortと同じで、
+// 長さを2のべき乗に切り上げた仮想的な列の比較のうち、実在する要素同士のものだけを行う
+// 並列に処理する閾値と、小さなブロックのソート方法はconfigで指定する
+pub(crate) fn do_sort<T, V, F>(mut x: Columns<T, V>, forward: bool, comparator: &F, config: &Sorter)
 where
     T: Send,
-    F: Sync + Fn(&T, &T) -> Ordering,
+    V: Send,
+    F: Exchange<T, V>,
 {
+    if let BaseCase::Insertion { max_len } = config.get_base_case() {
+        if x.len() <= max_len {
+            insertion_sort(x, forward, comparator);
+            return;
+        }
+    }
     if x.len() > 1 {
-        let mid_point = x.len() / 2;
+        let threshold = config.get_parallel_threshold();
+        // x.len()未満で最大の2のべき乗で分割する
+        let mid_point = power_of_two_below(x.len());
         // xをmid_pointを境にした2つの可変の借用に分割し
         // firstとsecondに束縛する
-        let (first, second) = x.split_at_mut(mid_point);
+        let (first, second) = x.reborrow().split_at(mid_point);
         // xの分割後の閾値と比較する
-        if mid_point >= PARALLEL_THRESHOLD {
+        if mid_point >= threshold {
             // 閾値以上なら並列にソートする
             rayon::join(
-                || do_sort(first, true, comparator),
-                || do_sort(second, false, comparator),
+                || do_sort(first, forward, comparator, config),
+                || do_sort(second, forward, comparator, config),
             );
         } else {
             // 閾値未満なら順番にソートする
-            // 第2引数がtrueのときはcomparatorで示される順にソート
-            do_sort(&mut x[..mid_point], true, comparator);
-            // 第2引数がfalseのときはcomparatorとは逆順にソート
-            do_sort(&mut x[mid_point..], false, comparator);
+            // 前半と後半をどちらもcomparatorで示される順にソートする
+            do_sort(first, forward, comparator, config);
+            do_sort(second, forward, comparator, config);
+        }
+        // 後半を逆順に読むと、全体がバイトニック列になる
+        flip_and_swap(x.reborrow(), forward, comparator, threshold);
+        let (first, second) = x.split_at(mid_point);
+        if mid_point >= threshold {
+            rayon::join(
+                || sub_sort(first, forward, comparator, threshold),
+                || sub_sort(second, forward, comparator, threshold),
+            );
+        } else {
+            sub_sort(first, forward, comparator, threshold);
+            sub_sort(second, forward, comparator, threshold);
         }
-        sub_sort(x, forward, comparator)
     }
 }
 
-fn sub_sort<T, F>(x: &mut [T], forward: bool, comparator: &F)
+fn sub_sort<T, V, F>(mut x: Columns<T, V>, forward: bool, comparator: &F, threshold: usize)
 where
     T: Send,
-    F: Sync + Fn(&T, &T) -> Ordering,
+    V: Send,
+    F: Exchange<T, V>,
 {
     // 受け取ったforward引数をcompare_and_swap関数や自分自身の再帰呼び出しにそのまま渡す
     if x.len() > 1 {
-        compare_and_swap(x, forward, comparator);
-        let mid_point = x.len() / 2;
-        let (first, second) = x.split_at_mut(mid_point);
+        compare_and_swap(x.reborrow(), forward, comparator, threshold);
+        let mid_point = power_of_two_below(x.len());
+        let (first, second) = x.split_at(mid_point);
         // xの分割後の閾値と比較する
-        if mid_point >= PARALLEL_THRESHOLD {
+        if mid_point >= threshold {
             // 閾値以上なら並列にソートする
             rayon::join(
-                || sub_sort(first, forward, comparator),
-                || sub_sort(second, forward, comparator),
+                || sub_sort(first, forward, comparator, threshold),
+                || sub_sort(second, forward, comparator, threshold),
             );
         } else {
             // 閾値未満なら順番にソートする
-            // 第2引数がtrueのときはcomparatorで示される順にソート
-            sub_sort(first, forward, comparator);
-            // 第2引数がfalseのときはcomparatorとは逆順にソート
-            sub_sort(second, forward, comparator);
+            sub_sort(first, forward, comparator, threshold);
+            sub_sort(second, forward, comparator, threshold);
+        }
+    }
+}
+
+// ソート済みのfirstとsecondをマージする
+// 2つを同じ長さ(2のべき乗)の列とみなし、firstの前には「どの要素よりも前に並ぶ値」、
+// secondの後ろには「どの要素よりも後ろに並ぶ値」が詰まっているものとして
+// do_sortの後半と同じマージを行う。詰めた値は交換されないので比較を省略する
+fn merge<T, V, F>(
+    mut first: Columns<T, V>,
+    mut second: Columns<T, V>,
+    forward: bool,
+    comparator: &F,
+    threshold: usize,
+) where
+    T: Send,
+    V: Send,
+    F: Exchange<T, V>,
+{
+    flip_between(
+        first.reborrow(),
+        second.reborrow(),
+        forward,
+        comparator,
+        threshold,
+    );
+    if first.len().max(second.len()) >= threshold {
+        rayon::join(
+            || sub_sort_back(first, forward, comparator, threshold),
+            || sub_sort(second, forward, comparator, threshold),
+        );
+    } else {
+        sub_sort_back(first, forward, comparator, threshold);
+        sub_sort(second, forward, comparator, threshold);
+    }
+}
+
+// mergeの前半だけを行い、firstとsecondを合わせた中で先に並ぶfirst.len()個を
+// firstにソートして集める。secondの並びは問わない
+fn keep_front<T, V, F>(
+    mut first: Columns<T, V>,
+    second: Columns<T, V>,
+    forward: bool,
+    comparator: &F,
+    threshold: usize,
+) where
+    T: Send,
+    V: Send,
+    F: Exchange<T, V>,
+{
+    flip_between(first.reborrow(), second, forward, comparator, threshold);
+    sub_sort_back(first, forward, comparator, threshold);
+}
+
+// firstの末尾から前に向かって、secondの先頭から後ろに向かって要素を比較する
+// この後、firstには先に並ぶ要素が、secondには後に並ぶ要素が集まる
+// 比較する組の数が閾値以上なら、firstは末尾から、secondは先頭から閾値個ずつのチャンクに分け、
+// 対応するチャンクの組をrayonのスレッドプールに分配する
+fn flip_between<T, V, F>(
+    first: Columns<T, V>,
+    second: Columns<T, V>,
+    forward: bool,
+    comparator: &F,
+    threshold: usize,
+) where
+    T: Send,
+    V: Send,
+    F: Exchange<T, V>,
+{
+    // 比較に使うのはfirstの末尾とsecondの先頭の、短い方の長さの分だけ
+    let pairs = first.len().min(second.len());
+    let first_len = first.len();
+    let (_, first) = first.split_at(first_len - pairs);
+    let (second, _) = second.split_at(pairs);
+    if pairs >= threshold {
+        // 端数はfirstの先頭とsecondの末尾に残し、残りを閾値個ずつのチャンクにする
+        let chunk_len = threshold.max(1);
+        let rest = pairs % chunk_len;
+        let (first_rest, first) = first.split_at(rest);
+        let (second, second_rest) = second.split_at(pairs - rest);
+        first
+            .par_chunks(chunk_len)
+            .rev()
+            .zip(second.par_chunks(chunk_len))
+            .for_each(|(first, second)| comparator.flip_and_swap(first, second, forward));
+        comparator.flip_and_swap(first_rest, second_rest, forward);
+    } else {
+        comparator.flip_and_swap(first, second, forward);
+    }
+}
+
+// xをk個ずつのブロックに分け、ブロックの列を半分ずつに分けて並列に処理する
+// それぞれの先頭に集めたk個から、バイトニックマージの前半だけで先に並ぶk個を選ぶ
+fn partial<T, V, F>(mut x: Columns<T, V>, k: usize, comparator: &F, config: &Sorter)
+where
+    T: Send,
+    V: Send,
+    F: Exchange<T, V>,
+{
+    if x.len() <= k {
+        do_sort(x, true, comparator, config);
+        return;
+    }
+    let threshold = config.get_parallel_threshold();
+    let blocks = x.len().div_ceil(k);
+    let (mut first, mut second) = x.reborrow().split_at(blocks / 2 * k);
+    if second.len() >= threshold {
+        rayon::join(
+            || partial(first.reborrow(), k, comparator, config),
+            || partial(second.reborrow(), k, comparator, config),
+        );
+    } else {
+        partial(first.reborrow(), k, comparator, config);
+        partial(second.reborrow(), k, comparator, config);
+    }
+    let second_len = second.len().min(k);
+    keep_front(
+        first.split_at(k).0,
+        second.split_at(second_len).0,
+        true,
+        comparator,
+        threshold,
+    );
+}
+
+// 前に値が詰まっているとみなす版のsub_sort
+// 比較する組はsub_sortと同じで、後ろから2のべき乗の長さで分割して再帰する
+fn sub_sort_back<T, V, F>(mut x: Columns<T, V>, forward: bool, comparator: &F, threshold: usize)
+where
+    T: Send,
+    V: Send,
+    F: Exchange<T, V>,
+{
+    if x.len() > 1 {
+        compare_and_swap(x.reborrow(), forward, comparator, threshold);
+        let mid_point = x.len() - power_of_two_below(x.len());
+        let (first, second) = x.split_at(mid_point);
+        if second.len() >= threshold {
+            rayon::join(
+                || sub_sort_back(first, forward, comparator, threshold),
+                || sub_sort_back(second, forward, comparator, threshold),
+            );
+        } else {
+            sub_sort_back(first, forward, comparator, threshold);
+            sub_sort_back(second, forward, comparator, threshold);
         }
     }
 }
 
-// 2つの要素の比較にcomparatorクロージャを使う
-fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F)
+// 小さなブロック用の挿入ソート
+// 前の要素と比較して、swap_conditionを満たす間は前へ移動させる
+fn insertion_sort<T, V, F>(x: Columns<T, V>, forward: bool, comparator: &F)
 where
-    F: Fn(&T, &T) -> Ordering,
+    F: Exchange<T, V>,
 {
-    // 比較に先立ちforward(bool値)をOrdering値に変換しておく
-    let swap_condition = if forward {
+    let swap_condition = swap_condition(forward);
+    for i in 1..x.len() {
+        let mut j = i;
+        while j > 0 && comparator.compare(&x.keys[j - 1], &x.keys[j]) == swap_condition {
+            x.keys.swap(j - 1, j);
+            x.values.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+}
+
+// ネットワークでソートする列
+// keysの要素を交換するたびに、valuesの同じ位置の要素も交換する
+// pairsはキーと値の組のソートに使い、値のないソートではvaluesを()の列にする
+pub(crate) struct Columns<'a, T, V> {
+    pub(crate) keys: &'a mut [T],
+    pub(crate) values: &'a mut [V],
+}
+
+impl<'a, T> Columns<'a, T, ()> {
+    // 値のない列
+    // ()の列はメモリを確保しないので、leakしても失われるものはない
+    pub(crate) fn keys(keys: &'a mut [T]) -> Self {
+        let values = vec![(); keys.len()].leak();
+        Columns { keys, values }
+    }
+}
+
+impl<'a, T, V> Columns<'a, T, V> {
+    // keysとvaluesは同じ長さでなければならない
+    pub(crate) fn new(keys: &'a mut [T], values: &'a mut [V]) -> Self {
+        assert_eq!(keys.len(), values.len());
+        Columns { keys, values }
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    // 再帰呼び出しに渡すために、短い期間だけ借用し直す
+    fn reborrow(&mut self) -> Columns<'_, T, V> {
+        Columns {
+            keys: self.keys,
+            values: self.values,
+        }
+    }
+
+    // keysとvaluesを同じ位置で2つに分ける
+    fn split_at(self, mid: usize) -> (Self, Self) {
+        let (first_keys, second_keys) = self.keys.split_at_mut(mid);
+        let (first_values, second_values) = self.values.split_at_mut(mid);
+        (
+            Columns {
+                keys: first_keys,
+                values: first_values,
+            },
+            Columns {
+                keys: second_keys,
+                values: second_values,
+            },
+        )
+    }
+
+    // chunk_len個ずつに分けて、rayonで並列に処理できるようにする
+    fn par_chunks(self, chunk_len: usize) -> impl IndexedParallelIterator<Item = Columns<'a, T, V>>
+    where
+        T: Send,
+        V: Send,
+    {
+        self.keys
+            .par_chunks_mut(chunk_len)
+            .zip(self.values.par_chunks_mut(chunk_len))
+            .map(|(keys, values)| Columns { keys, values })
+    }
+}
+
+// 前半と後半の要素の組を比較交換する方法
+// 比較関数のクロージャはそのままExchangeとして使え、1組ずつcompareで比較して交換する
+// simdモジュールは、u32やi32の組をAVX2でまとめて比較交換する実装を渡す
+pub(crate) trait Exchange<T, V = ()>: Sync {
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+
+    // first[i]とsecond[i]を比較交換する
+    fn compare_and_swap(&self, first: Columns<T, V>, second: Columns<T, V>, forward: bool) {
+        let swap_condition = swap_condition(forward);
+        let keys = first.keys.iter_mut().zip(second.keys.iter_mut());
+        let values = first.values.iter_mut().zip(second.values.iter_mut());
+        for ((a, b), (va, vb)) in keys.zip(values) {
+            swap_if(a, b, va, vb, self, swap_condition);
+        }
+    }
+
+    // first[first.len() - 1 - i]とsecond[i]を比較交換する
+    fn flip_and_swap(&self, first: Columns<T, V>, second: Columns<T, V>, forward: bool) {
+        let swap_condition = swap_condition(forward);
+        let keys = first.keys.iter_mut().rev().zip(second.keys.iter_mut());
+        let values = first.values.iter_mut().rev().zip(second.values.iter_mut());
+        for ((a, b), (va, vb)) in keys.zip(values) {
+            swap_if(a, b, va, vb, self, swap_condition);
+        }
+    }
+}
+
+impl<T, V, F> Exchange<T, V> for F
+where
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
+    }
+}
+
+// 比較に先立ちforward(bool値)をOrdering値に変換しておく
+fn swap_condition(forward: bool) -> Ordering {
+    if forward {
         Ordering::Greater
     } else {
         Ordering::Less
-    };
-    let mid_point = x.len() / 2;
-    for i in 0..mid_point {
-        // comparatorクロージャで2要素を比較し、返されたOrderingのバリアントが
-        // swap_conditionと等しいなら要素を交換する
-        // comparatorクロージャはGreater, Equal, Lessのいずれかのバリアントを返す
-        // この値とforward(bool型)は比較できないため、上でOrdering型へ変換した
-        if comparator(&x[i], &x[mid_point + i]) == swap_condition {
-            // 2つの要素を交換するswapメソッドを使う
-            x.swap(i, mid_point + i)
-        }
+    }
+}
+
+// 前半の末尾から前に向かって、後半の先頭から後ろに向かって要素を比較する
+fn flip_and_swap<T, V, F>(x: Columns<T, V>, forward: bool, comparator: &F, threshold: usize)
+where
+    T: Send,
+    V: Send,
+    F: Exchange<T, V>,
+{
+    let mid_point = power_of_two_below(x.len());
+    let (first, second) = x.split_at(mid_point);
+    flip_between(first, second, forward, comparator, threshold);
+}
+
+// 前半と後半をsplit_atで分けておけば、組ごとに別々の可変の借用が得られるので、
+// 組同士は互いに独立に並列に比較交換できる
+fn compare_and_swap<T, V, F>(x: Columns<T, V>, forward: bool, comparator: &F, threshold: usize)
+where
+    T: Send,
+    V: Send,
+    F: Exchange<T, V>,
+{
+    // 後半が前半より短いときは、後半の長さの分だけ比較する
+    let mid_point = power_of_two_below(x.len());
+    let pairs = x.len() - mid_point;
+    let (first, second) = x.split_at(mid_point);
+    let (first, _) = first.split_at(pairs);
+    if pairs >= threshold {
+        // 閾値個ずつのチャンクに分けて、前半と後半の対応するチャンクを並列に処理する
+        let chunk_len = threshold.max(1);
+        first
+            .par_chunks(chunk_len)
+            .zip(second.par_chunks(chunk_len))
+            .for_each(|(first, second)| comparator.compare_and_swap(first, second, forward));
+    } else {
+        comparator.compare_and_swap(first, second, forward);
+    }
+}
+
+// comparatorで2要素を比較し、返されたOrderingのバリアントが
+// swap_conditionと等しいなら要素を交換する。値も同じように交換する
+// comparatorはGreater, Equal, Lessのいずれかのバリアントを返す
+// この値とforward(bool型)は比較できないため、上でOrdering型へ変換した
+fn swap_if<T, V, F>(
+    a: &mut T,
+    b: &mut T,
+    va: &mut V,
+    vb: &mut V,
+    comparator: &F,
+    swap_condition: Ordering,
+) where
+    F: Exchange<T, V> + ?Sized,
+{
+    if comparator.compare(a, b) == swap_condition {
+        std::mem::swap(a, b);
+        std::mem::swap(va, vb);
     }
 }
 
@@ -132,9 +659,17 @@ where
 #[cfg(test)]
 mod tests {
     // 親モジュール(first)のsort関数を使用する
-    use super::{sort, sort_by};
+    use super::{
+        argsort, argsort_by, merge_sorted, merge_sorted_halves, partial_sort, sort, sort_by,
+        sort_by_cached_key, sort_by_key, stable_sort_by, top_k,
+    };
+    use crate::network::Network;
+    use crate::permutation::apply_permutation;
+    use crate::sorter::Sorter;
     use crate::utils::{is_sorted_ascending, is_sorted_descending, new_u32_vec};
+    use crate::zero_one::verify;
     use crate::SortOrder::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     // deriveアトリビュートを使い、DebugトレイトとPartialEqトレイトの実装を自動導出する
     #[derive(Debug, PartialEq)]
@@ -222,6 +757,114 @@ mod tests {
         assert_eq!(x, expected);
     }
 
+    #[test]
+    fn sort_student_by_lowercase_last_name() {
+        // 大文字と小文字が混ざった苗字を小文字にしてから比較する
+        let taro = Student::new("Taro", "Yamada", 16);
+        let hanako = Student::new("Hanako", "sato", 14);
+        let kyoko = Student::new("Kyoko", "ito", 15);
+        let ryosuke = Student::new("Ryosuke", "Hayashi", 17);
+
+        let mut x = vec![&taro, &hanako, &kyoko, &ryosuke];
+        let expected = vec![&ryosuke, &kyoko, &hanako, &taro];
+        assert_eq!(sort_by_key(&mut x, &|s| s.last_name.to_lowercase()), Ok(()));
+        assert_eq!(x, expected);
+
+        let mut x = vec![&taro, &hanako, &kyoko, &ryosuke];
+        assert_eq!(
+            sort_by_cached_key(&mut x, &|s| s.last_name.to_lowercase()),
+            Ok(())
+        );
+        assert_eq!(x, expected);
+    }
+
+    #[test]
+    fn sort_by_cached_key_computes_each_key_once() {
+        // 2のべき乗でない長さでも、キーの計算は要素数と同じ回数だけ
+        let calls = AtomicUsize::new(0);
+        let mut x = new_u32_vec(1000);
+        let mut expected = x.clone();
+        expected.sort_by_key(|v| v % 1000);
+        assert_eq!(
+            sort_by_cached_key(&mut x, &|v| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                v % 1000
+            }),
+            Ok(())
+        );
+        assert_eq!(calls.load(Ordering::Relaxed), 1000);
+        // キーが等しい要素は元の順のまま残るので、安定ソートの結果と一致する
+        assert_eq!(x, expected);
+    }
+
+    #[test]
+    fn argsort_keeps_input() {
+        let x = vec![10, 30, 11, 20, 4, 330, 21];
+        let order: Vec<usize> = argsort(&x, &Ascending).unwrap();
+        assert_eq!(order, vec![4, 0, 2, 3, 6, 1, 5]);
+        // 入力は変更されない
+        assert_eq!(x, vec![10, 30, 11, 20, 4, 330, 21]);
+
+        // 添字をu32で受け取ることもできる
+        let order: Vec<u32> = argsort(&x, &Descending).unwrap();
+        assert_eq!(order, vec![5, 1, 6, 3, 2, 0, 4]);
+    }
+
+    #[test]
+    fn argsort_student_columns() {
+        // 列ごとに分かれたデータを、年齢の列の並べ替えで一緒に並べ替える
+        let mut ages = vec![16, 14, 15, 17, 14];
+        let mut names = vec!["Taro", "Hanako", "Kyoko", "Ryosuke", "Ichiro"];
+        let order: Vec<u32> = argsort_by(&ages, &|a, b| a.cmp(b)).unwrap();
+        // 年齢が等しいHanakoとIchiroは元の順に並ぶ
+        assert_eq!(order, vec![1, 4, 2, 0, 3]);
+        apply_permutation(&order, &mut [&mut ages, &mut names]);
+        assert_eq!(ages, vec![14, 14, 15, 16, 17]);
+        assert_eq!(names, vec!["Hanako", "Ichiro", "Kyoko", "Taro", "Ryosuke"]);
+    }
+
+    #[test]
+    fn argsort_large() {
+        let x = new_u32_vec(65537);
+        let order: Vec<usize> = argsort(&x, &Ascending).unwrap();
+        let sorted: Vec<u32> = order.iter().map(|&i| x[i]).collect();
+        assert!(is_sorted_ascending(&sorted));
+    }
+
+    #[test]
+    fn stable_sort_student_by_age() {
+        // 同じ年齢の学生は元の順のまま並ぶ
+        let taro = Student::new("Taro", "Yamada", 16);
+        let hanako = Student::new("Hanako", "Yamada", 14);
+        let kyoko = Student::new("Kyoko", "Ito", 15);
+        let ryosuke = Student::new("Ryosuke", "Hayashi", 16);
+        let ichiro = Student::new("Ichiro", "Sato", 14);
+
+        let mut x = vec![&taro, &hanako, &kyoko, &ryosuke, &ichiro];
+        let expected = vec![&hanako, &ichiro, &kyoko, &taro, &ryosuke];
+        assert_eq!(stable_sort_by(&mut x, &|a, b| a.age.cmp(&b.age)), Ok(()));
+        assert_eq!(x, expected);
+    }
+
+    #[test]
+    fn stable_sort_keeps_tag_order() {
+        // 元の位置をタグとして持たせた組を、重複の多いキーだけで比較してソートする
+        // 同じキーの組のタグが昇順のままなら、元の順が保たれている
+        for &len in [1, 7, 100, 4097, 20000].iter() {
+            let mut x: Vec<(u32, usize)> = new_u32_vec(len)
+                .into_iter()
+                .map(|v| v % 8)
+                .enumerate()
+                .map(|(tag, key)| (key, tag))
+                .collect();
+            assert_eq!(stable_sort_by(&mut x, &|a, b| a.0.cmp(&b.0)), Ok(()));
+            for pair in x.windows(2) {
+                let ((key0, tag0), (key1, tag1)) = (pair[0], pair[1]);
+                assert!(key0 < key1 || (key0 == key1 && tag0 < tag1), "len: {}", len);
+            }
+        }
+    }
+
     // テストケースになる関数は#[test]アトリビュートを付ける
     #[test]
     fn sort_u32_ascending() {
@@ -304,8 +947,165 @@ mod tests {
     }
 
     #[test]
-    fn sort_to_fail() {
+    fn sort_borrowed_str() {
+        // 'staticでない借用を要素にしてもソートできる
+        let text = String::from("Rust is fast and memory-efficient");
+        let mut x: Vec<&str> = text.split(' ').collect();
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec!["Rust", "and", "fast", "is", "memory-efficient"]);
+        assert_eq!(Sorter::new().sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec!["memory-efficient", "is", "fast", "and", "Rust"]);
+    }
+
+    #[test]
+    fn sort_odd_length() {
         let mut x = vec![10, 30, 11]; // 2のべき乗でない
-        assert!(sort(&mut x, &Ascending).is_err()); // 戻り値はErr
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![10, 11, 30]);
+
+        let mut x = vec![10, 30, 11, 20, 4, 330, 21];
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 30, 21, 20, 11, 10, 4]);
+    }
+
+    #[test]
+    fn sort_prime_and_off_by_one_lengths() {
+        // 素数の長さと、2のべき乗の前後の長さを試す
+        // 8191と8193は並列に処理される長さ
+        let lengths = [0, 1, 2, 5, 13, 31, 33, 127, 129, 1021, 8191, 8193, 65537];
+        for &len in lengths.iter() {
+            let mut x = new_u32_vec(len);
+            let mut expected = x.clone();
+            expected.sort();
+            assert_eq!(sort(&mut x, &Ascending), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+
+            let mut x = new_u32_vec(len);
+            assert_eq!(sort(&mut x, &Descending), Ok(()));
+            assert!(is_sorted_descending(&x), "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn zero_one_principle_up_to_24() {
+        // このモジュールのsort_byが実行するネットワークを、0と1のすべての入力で検証する
+        for len in 0..=24 {
+            let network = Network::record(len, |x, c| sort_by(x, c).unwrap());
+            assert_eq!(verify(&network), Ok(()), "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn parallel_compare_and_swap_executes_the_same_network() {
+        // 比較交換の組をスレッドに分配しても、実行するネットワークは変わらない
+        for &len in [2, 3, 100, 257, 1000].iter() {
+            let sorter = Sorter::new().parallel_threshold(4);
+            let network = Network::record(len, |x, c| sorter.sort_by(x, c).unwrap());
+            assert_eq!(
+                network.layers(),
+                Network::bitonic(len).layers(),
+                "len: {}",
+                len
+            );
+        }
+        // 等しい要素があっても、順次に比較交換したときと同じ結果になる
+        let original: Vec<(u32, usize)> = new_u32_vec(5000)
+            .into_iter()
+            .map(|v| v % 100)
+            .zip(0..)
+            .collect();
+        let key = |v: &(u32, usize)| v.0;
+        let mut par = original.clone();
+        Sorter::new()
+            .parallel_threshold(16)
+            .sort_by_key(&mut par, &key)
+            .unwrap();
+        let mut seq = original;
+        Sorter::new()
+            .parallel_threshold(usize::MAX)
+            .sort_by_key(&mut seq, &key)
+            .unwrap();
+        assert_eq!(par, seq);
+    }
+
+    #[test]
+    fn merge_sorted_halves_of_zeros_and_ones() {
+        // マージの比較器ネットワークにも0-1原理が使える
+        // 長さ12までのソート済みの0と1の列の組をすべて試す
+        for first_len in 0..=12 {
+            for second_len in 0..=12 {
+                for first_ones in 0..=first_len {
+                    for second_ones in 0..=second_len {
+                        let mut x = vec![0; first_len - first_ones];
+                        x.resize(first_len, 1);
+                        x.resize(first_len + second_len - second_ones, 0);
+                        x.resize(first_len + second_len, 1);
+                        assert_eq!(merge_sorted_halves(&mut x, first_len, &Ascending), Ok(()));
+                        assert!(is_sorted_ascending(&x), "{:?}", x);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn merge_sorted_large_shards() {
+        // 閾値を超える長さで、並列にマージする経路を通す
+        for &(a_len, b_len) in [(0, 5000), (1, 10000), (20000, 3), (8193, 12000)].iter() {
+            let mut a = new_u32_vec(a_len);
+            let mut b = new_u32_vec(b_len);
+            for &order in [Ascending, Descending].iter() {
+                sort(&mut a, &order).unwrap();
+                sort(&mut b, &order).unwrap();
+                let mut expected = [a.clone(), b.clone()].concat();
+                sort(&mut expected, &order).unwrap();
+                assert_eq!(merge_sorted(&a, &b, &order), Ok(expected.clone()));
+
+                let mut x = [a.clone(), b.clone()].concat();
+                assert_eq!(merge_sorted_halves(&mut x, a_len, &order), Ok(()));
+                assert_eq!(x, expected, "{} + {}", a_len, b_len);
+            }
+        }
+    }
+
+    #[test]
+    fn partial_sort_matches_full_sort() {
+        // 閾値を超える長さで、ブロックを並列に処理する経路も通す
+        for &len in [0, 1, 33, 1000, 20000].iter() {
+            for &k in [0, 1, 7, 32, 999, 5000, 20000].iter() {
+                for &order in [Ascending, Descending].iter() {
+                    let original = new_u32_vec(len);
+                    let mut expected = original.clone();
+                    sort(&mut expected, &order).unwrap();
+
+                    let mut x = original;
+                    assert_eq!(partial_sort(&mut x, k, &order), Ok(()));
+                    let k = k.min(len);
+                    assert_eq!(x[..k], expected[..k], "len: {}, k: {}", len, k);
+                    // 残りの要素も失われていない
+                    sort(&mut x, &order).unwrap();
+                    assert_eq!(x, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn top_k_matches_full_sort() {
+        for &len in [0, 5, 4097, 20000].iter() {
+            let x = new_u32_vec(len);
+            for &k in [0, 1, 100, 5000].iter() {
+                let mut expected = x.clone();
+                sort(&mut expected, &Ascending).unwrap();
+                expected.truncate(k);
+                assert_eq!(
+                    top_k(x.iter().cloned(), k, &Ascending),
+                    Ok(expected),
+                    "len: {}, k: {}",
+                    len,
+                    k
+                );
+            }
+        }
     }
 }
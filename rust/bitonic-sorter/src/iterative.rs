@@ -0,0 +1,422 @@
+// 再帰を使わないバイトニックソート
+// third/fourthのdo_sort/sub_sortが再帰で実行している比較器のネットワークを
+// 段数kと比較距離jの二重ループで、段ごとにまとめて実行する
+// 比較器の組と順序はthird::sort_byと同じなので、同じ比較結果から同じ並びが得られる
+// 大きな列では、キャッシュに収まるタイルごとに段をまとめるExecutionOrder::CacheBlockedも選べる
+
+use super::sorter::Sorter;
+use super::{power_of_two_below, SortError, SortOrder};
+use rayon::prelude::*;
+use std::cmp::Ordering;
+
+// 比較器を実行する順序
+// どちらの順序でも実行する比較器の組は同じで、同じ比較結果から同じ並びが得られる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionOrder {
+    // 1段ごとに列全体を処理する
+    // 列がキャッシュに収まらないと、段ごとに列全体をメモリから読み直すことになる
+    Stagewise,
+    // tile_len個ずつに区切ったタイルの中で完結する段は、タイルごとにまとめて処理する
+    // 1つのタイルをキャッシュに載せたまま最後の段まで進めてから、次のタイルに移る
+    // tile_lenは2のべき乗に切り下げる。要素の大きさ×tile_lenがL2キャッシュに収まるようにする
+    CacheBlocked { tile_len: usize },
+}
+
+// 順次版
+pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    sort_by_with(x, comparator, ExecutionOrder::Stagewise)
+}
+
+// 実行順序を指定してソートする
+pub fn sort_by_with<T, F>(
+    x: &mut [T],
+    comparator: &F,
+    execution: ExecutionOrder,
+) -> Result<(), SortError>
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    let len = x.len();
+    match execution {
+        ExecutionOrder::Stagewise => run_stages(len, |block_len, distance| {
+            pass(x, 0, len, block_len, distance, comparator)
+        }),
+        ExecutionOrder::CacheBlocked { tile_len } => {
+            let tile_len = round_tile_len(tile_len, len);
+            // タイルに収まるブロックは、タイルごとにソートし終えてしまう
+            for (t, tile) in x.chunks_mut(tile_len).enumerate() {
+                sort_tile(tile, t * tile_len, len, tile_len, comparator);
+            }
+            run_large_merges(len, tile_len, |block_len, distance| {
+                if distance >= tile_len {
+                    // タイルをまたぐ比較は列全体を1段ずつ処理する
+                    pass(x, 0, len, block_len, distance, comparator);
+                } else {
+                    // 残りの段はタイルの中で完結する
+                    for (t, tile) in x.chunks_mut(tile_len).enumerate() {
+                        merge_tile(tile, t * tile_len, len, block_len, distance, comparator);
+                    }
+                }
+            });
+        }
+    }
+    Ok(())
+}
+
+// 並列版
+// 各段の比較器は互いに独立しているので、段の中をrayonで分担する
+pub fn par_sort<T: Ord + Send>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => par_sort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => par_sort_by(x, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn par_sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+where
+    T: Send,
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    par_sort_by_with(x, comparator, ExecutionOrder::Stagewise)
+}
+
+// 実行順序を指定して並列にソートする
+// CacheBlockedでは、タイルの中で完結する段をタイルごとに別々のスレッドで処理する
+pub fn par_sort_by_with<T, F>(
+    x: &mut [T],
+    comparator: &F,
+    execution: ExecutionOrder,
+) -> Result<(), SortError>
+where
+    T: Send,
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    // 並列に処理する閾値は既定の設定のSorterと同じ値を使う
+    let threshold = Sorter::new().resolve::<T>().get_parallel_threshold();
+    let len = x.len();
+    match execution {
+        ExecutionOrder::Stagewise => run_stages(len, |block_len, distance| {
+            par_pass(x, len, block_len, distance, comparator, threshold)
+        }),
+        ExecutionOrder::CacheBlocked { tile_len } => {
+            let tile_len = round_tile_len(tile_len, len);
+            x.par_chunks_mut(tile_len)
+                .enumerate()
+                .for_each(|(t, tile)| sort_tile(tile, t * tile_len, len, tile_len, comparator));
+            run_large_merges(len, tile_len, |block_len, distance| {
+                if distance >= tile_len {
+                    par_pass(x, len, block_len, distance, comparator, threshold);
+                } else {
+                    x.par_chunks_mut(tile_len)
+                        .enumerate()
+                        .for_each(|(t, tile)| {
+                            merge_tile(tile, t * tile_len, len, block_len, distance, comparator)
+                        });
+                }
+            });
+        }
+    }
+    Ok(())
+}
+
+// バイトニックソートの段を順番に呼び出す
+// block_lenはマージしているブロックの長さk、distanceは比較する2要素の距離j
+// 長さを2のべき乗に切り上げた仮想的な列で考え、実在しない要素との比較は省略する
+fn run_stages<S>(len: usize, mut stage: S)
+where
+    S: FnMut(usize, usize),
+{
+    let mut block_len = 2;
+    while block_len < len * 2 {
+        let mut distance = block_len / 2;
+        while distance > 0 {
+            stage(block_len, distance);
+            distance /= 2;
+        }
+        block_len *= 2;
+    }
+}
+
+// タイルより長いブロックのマージを呼び出す
+// 距離がタイルの長さ以上の段は1段ずつ、それより近い段はまとめて1回だけ呼び出す
+// まとめて呼び出すときのdistanceは、タイルの中で行う最初の段の距離
+fn run_large_merges<S>(len: usize, tile_len: usize, mut stage: S)
+where
+    S: FnMut(usize, usize),
+{
+    let mut block_len = tile_len * 2;
+    while block_len < len * 2 {
+        let mut distance = block_len / 2;
+        while distance >= tile_len {
+            stage(block_len, distance);
+            distance /= 2;
+        }
+        stage(block_len, distance);
+        block_len *= 2;
+    }
+}
+
+// 2以上の2のべき乗に切り下げる
+// 列より長いタイルは意味がないので、列の長さまでに抑える
+fn round_tile_len(tile_len: usize, len: usize) -> usize {
+    power_of_two_below(tile_len.min(len).max(2) + 1)
+}
+
+// tile_start番目から始まるタイルの中で、長さtile_len以下のブロックをすべてソートする
+fn sort_tile<T, F>(tile: &mut [T], tile_start: usize, len: usize, tile_len: usize, comparator: &F)
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    let mut block_len = 2;
+    while block_len <= tile_len && block_len < len * 2 {
+        merge_tile(tile, tile_start, len, block_len, block_len / 2, comparator);
+        block_len *= 2;
+    }
+}
+
+// タイルの中で、block_lenのマージのうち距離max_distance以下の段をすべて行う
+fn merge_tile<T, F>(
+    tile: &mut [T],
+    tile_start: usize,
+    len: usize,
+    block_len: usize,
+    max_distance: usize,
+    comparator: &F,
+) where
+    F: Fn(&T, &T) -> Ordering,
+{
+    let mut distance = max_distance;
+    while distance > 0 {
+        pass(tile, tile_start, len, block_len, distance, comparator);
+        distance /= 2;
+    }
+}
+
+// xの全体に対して1段分の比較交換を行う
+// x_startはxの先頭が元の列の何番目か
+fn pass<T, F>(
+    x: &mut [T],
+    x_start: usize,
+    len: usize,
+    block_len: usize,
+    distance: usize,
+    comparator: &F,
+) where
+    F: Fn(&T, &T) -> Ordering,
+{
+    for (i, chunk) in x.chunks_mut(distance * 2).enumerate() {
+        if is_active(x_start + i * distance * 2, block_len, len) {
+            stage(chunk, block_len, distance, comparator);
+        }
+    }
+}
+
+// passの並列版
+fn par_pass<T, F>(
+    x: &mut [T],
+    len: usize,
+    block_len: usize,
+    distance: usize,
+    comparator: &F,
+    threshold: usize,
+) where
+    T: Send,
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    let chunk_len = distance * 2;
+    if chunk_len >= threshold {
+        // 塊が大きい上位の段では、塊の中の比較も並列に行う
+        x.par_chunks_mut(chunk_len)
+            .enumerate()
+            .filter(|(i, _)| is_active(i * chunk_len, block_len, len))
+            .for_each(|(_, chunk)| par_stage(chunk, block_len, distance, comparator, threshold));
+    } else {
+        // 塊が小さい段では、複数の塊をまとめて1つのタスクにする
+        x.par_chunks_mut(chunk_len)
+            .with_min_len(threshold / chunk_len)
+            .enumerate()
+            .filter(|(i, _)| is_active(i * chunk_len, block_len, len))
+            .for_each(|(_, chunk)| stage(chunk, block_len, distance, comparator));
+    }
+}
+
+// chunk_startから始まる塊をこの段で処理するか調べる
+// 塊が属するブロックの実在する要素が前半に収まっているなら、
+// そのブロックはすでにソート済みなので、再帰版と同じくマージを省略する
+fn is_active(chunk_start: usize, block_len: usize, len: usize) -> bool {
+    let block_start = chunk_start - chunk_start % block_len;
+    len - block_start > block_len / 2
+}
+
+// 1つの塊に対して1段分の比較交換を行う
+// ブロックの最初の段(distance == block_len / 2)では前半を逆順に読んで比較する
+fn stage<T, F>(chunk: &mut [T], block_len: usize, distance: usize, comparator: &F)
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    if chunk.len() <= distance {
+        return;
+    }
+    let (first, second) = chunk.split_at_mut(distance);
+    if distance == block_len / 2 {
+        for (a, b) in first.iter_mut().rev().zip(second.iter_mut()) {
+            compare_and_swap(a, b, comparator);
+        }
+    } else {
+        for (a, b) in first.iter_mut().zip(second.iter_mut()) {
+            compare_and_swap(a, b, comparator);
+        }
+    }
+}
+
+fn par_stage<T, F>(
+    chunk: &mut [T],
+    block_len: usize,
+    distance: usize,
+    comparator: &F,
+    threshold: usize,
+) where
+    T: Send,
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    if chunk.len() <= distance {
+        return;
+    }
+    let (first, second) = chunk.split_at_mut(distance);
+    if distance == block_len / 2 {
+        first
+            .par_iter_mut()
+            .rev()
+            .zip(second.par_iter_mut())
+            .with_min_len(threshold)
+            .for_each(|(a, b)| compare_and_swap(a, b, comparator));
+    } else {
+        first
+            .par_iter_mut()
+            .zip(second.par_iter_mut())
+            .with_min_len(threshold)
+            .for_each(|(a, b)| compare_and_swap(a, b, comparator));
+    }
+}
+
+// aがbより後ろに並ぶべきなら交換する
+fn compare_and_swap<T, F>(a: &mut T, b: &mut T, comparator: &F)
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    if comparator(a, b) == Ordering::Greater {
+        std::mem::swap(a, b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        par_sort, par_sort_by, par_sort_by_with, sort, sort_by, sort_by_with, ExecutionOrder,
+    };
+    use crate::network::Network;
+    use crate::third;
+    use crate::utils::{is_sorted_ascending, is_sorted_descending, new_u32_vec};
+    use crate::SortOrder::*;
+
+    #[test]
+    fn sort_u32_ascending() {
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![4, 10, 11, 20, 21, 30, 110, 330]);
+
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(par_sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![4, 10, 11, 20, 21, 30, 110, 330]);
+    }
+
+    #[test]
+    fn sort_u32_descending() {
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 110, 30, 21, 20, 11, 10, 4]);
+
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(par_sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 110, 30, 21, 20, 11, 10, 4]);
+    }
+
+    #[test]
+    fn sort_u32_large_and_odd_lengths() {
+        for &len in [0, 1, 2, 3, 13, 33, 1021, 8193, 65536].iter() {
+            let mut x = new_u32_vec(len);
+            assert_eq!(sort(&mut x, &Ascending), Ok(()));
+            assert!(is_sorted_ascending(&x), "len: {}", len);
+
+            let mut x = new_u32_vec(len);
+            assert_eq!(par_sort(&mut x, &Descending), Ok(()));
+            assert!(is_sorted_descending(&x), "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn same_result_as_third_sort_by() {
+        // 上位16bitだけで比較するので、キーが等しい要素が多数ある
+        // 同じネットワークなので、同じキーの要素の並びまでthird::sort_byと一致する
+        let comparator = |a: &u32, b: &u32| (a >> 16).cmp(&(b >> 16));
+        for &len in [5, 100, 4097, 20000].iter() {
+            let x: Vec<u32> = new_u32_vec(len).into_iter().map(|v| v >> 8).collect();
+            let mut expected = x.clone();
+            assert_eq!(third::sort_by(&mut expected, &comparator), Ok(()));
+
+            let mut seq = x.clone();
+            assert_eq!(sort_by(&mut seq, &comparator), Ok(()));
+            assert_eq!(seq, expected, "len: {}", len);
+
+            let mut par = x;
+            assert_eq!(par_sort_by(&mut par, &comparator), Ok(()));
+            assert_eq!(par, expected, "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn cache_blocked_order_gives_the_same_result() {
+        // タイルの長さや列の長さによらず、段ごとの実行と同じ並びになる
+        let comparator = |a: &u32, b: &u32| (a >> 16).cmp(&(b >> 16));
+        for &tile_len in [0, 2, 5, 64, 1 << 12, usize::MAX].iter() {
+            let execution = ExecutionOrder::CacheBlocked { tile_len };
+            for &len in [0, 1, 2, 63, 64, 65, 4097, 20000].iter() {
+                let x: Vec<u32> = new_u32_vec(len).into_iter().map(|v| v >> 8).collect();
+                let mut expected = x.clone();
+                assert_eq!(sort_by(&mut expected, &comparator), Ok(()));
+
+                let mut seq = x.clone();
+                assert_eq!(sort_by_with(&mut seq, &comparator, execution), Ok(()));
+                assert_eq!(seq, expected, "tile_len: {}, len: {}", tile_len, len);
+
+                let mut par = x;
+                assert_eq!(par_sort_by_with(&mut par, &comparator, execution), Ok(()));
+                assert_eq!(par, expected, "tile_len: {}, len: {}", tile_len, len);
+            }
+        }
+    }
+
+    #[test]
+    fn cache_blocked_order_executes_the_same_network() {
+        let execution = ExecutionOrder::CacheBlocked { tile_len: 8 };
+        for len in 0..=40 {
+            let network = Network::record(len, |x, c| sort_by_with(x, c, execution).unwrap());
+            assert_eq!(
+                network.layers(),
+                Network::bitonic(len).layers(),
+                "len: {}",
+                len
+            );
+        }
+    }
+}
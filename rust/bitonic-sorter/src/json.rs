@@ -0,0 +1,603 @@
+// 最小限のJSONパーサと、JSON Linesのソート
+// JSON Linesは1行に1つのJSONの値を書いたもので、
+// 各行から.request.latency_msのようなパスで取り出した値をキーにして行を並べ替える
+// 行は解析するだけで書き換えないので、出力は入力の行をバイト単位でそのまま並べ替えたものになる
+//
+// let mut lines: Vec<&str> = input.lines().collect();
+// json::sort_json_lines(&mut lines, ".user.name", &SortOrder::Ascending, MissingKeys::Last)?;
+
+use super::fourth;
+use super::permutation::permute_in_place;
+use super::{SortError, SortOrder};
+use std::cmp::Ordering;
+use std::{error, fmt};
+
+// 入れ子の深さの上限
+// 再帰で解析するので、深すぎる入力でスタックを使い果たさないようにする
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    // キーの順を保つためにVecで持つ
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    // オブジェクトのキーや配列の添字を.でつないだパスで値を取り出す
+    // 先頭の.は省略でき、"."や""は値そのものを表す
+    // 同じキーが複数あるときは最後のものを使う
+    // 例: ".request.latency_ms", "items.0.id"
+    pub fn get_path(&self, path: &str) -> Option<&Value> {
+        let path = path.strip_prefix('.').unwrap_or(path);
+        if path.is_empty() {
+            return Some(self);
+        }
+        path.split('.')
+            .try_fold(self, |value, segment| match value {
+                Value::Object(members) => members
+                    .iter()
+                    .rev()
+                    .find(|(key, _)| key == segment)
+                    .map(|(_, v)| v),
+                Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+                _ => None,
+            })
+    }
+}
+
+// lineとcolumnは1から数えた位置。columnはバイト単位
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    pub line: usize,
+    pub column: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (line: {}, column: {})",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl error::Error for JsonError {}
+
+// JSON Linesのソートのエラー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonLinesError {
+    Json(JsonError),
+    Sort(SortError),
+}
+
+impl fmt::Display for JsonLinesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonLinesError::Json(e) => write!(f, "{}", e),
+            JsonLinesError::Sort(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for JsonLinesError {}
+
+impl From<SortError> for JsonLinesError {
+    fn from(e: SortError) -> Self {
+        JsonLinesError::Sort(e)
+    }
+}
+
+// 1つのJSONの値を解析する。前後の空白以外の文字が残っていればエラーにする
+pub fn parse(input: &str) -> Result<Value, JsonError> {
+    let mut parser = Parser {
+        bytes: input.as_bytes(),
+        input,
+        position: 0,
+    };
+    parser.skip_whitespace();
+    let value = parser.parse_value(0)?;
+    parser.skip_whitespace();
+    if parser.position < parser.bytes.len() {
+        return Err(parser.error("unexpected characters after the value"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &'static str) -> JsonError {
+        let before = &self.bytes[..self.position.min(self.bytes.len())];
+        let line_start = before
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        JsonError {
+            line: before.iter().filter(|&&b| b == b'\n').count() + 1,
+            column: before.len() - line_start + 1,
+            message,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).cloned()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str, value: Value) -> Result<Value, JsonError> {
+        if self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            self.position += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("invalid literal"))
+        }
+    }
+
+    fn parse_value(&mut self, depth: usize) -> Result<Value, JsonError> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("too deeply nested"));
+        }
+        match self.peek() {
+            Some(b'n') => self.expect("null", Value::Null),
+            Some(b't') => self.expect("true", Value::Bool(true)),
+            Some(b'f') => self.expect("false", Value::Bool(false)),
+            Some(b'"') => self.parse_string().map(Value::String),
+            Some(b'[') => self.parse_array(depth),
+            Some(b'{') => self.parse_object(depth),
+            Some(b'-') | Some(b'0'..=b'9') => self.parse_number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn parse_array(&mut self, depth: usize) -> Result<Value, JsonError> {
+        self.position += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            self.skip_whitespace();
+            items.push(self.parse_value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Value::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self, depth: usize) -> Result<Value, JsonError> {
+        self.position += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            if self.peek() != Some(b':') {
+                return Err(self.error("expected ':'"));
+            }
+            self.position += 1;
+            self.skip_whitespace();
+            members.push((key, self.parse_value(depth + 1)?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    // -?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?
+    fn parse_number(&mut self) -> Result<Value, JsonError> {
+        let start = self.position;
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.position += 1,
+            Some(b'1'..=b'9') => {
+                self.skip_digits();
+            }
+            _ => return Err(self.error("invalid number")),
+        }
+        if self.peek() == Some(b'.') {
+            self.position += 1;
+            if !self.skip_digits() {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            self.position += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.position += 1;
+            }
+            if !self.skip_digits() {
+                return Err(self.error("invalid number"));
+            }
+        }
+        self.input[start..self.position]
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    // 数字を1つ以上読み飛ばしたらtrueを返す
+    fn skip_digits(&mut self) -> bool {
+        let start = self.position;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.position += 1;
+        }
+        self.position > start
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.position += 1;
+        let mut string = String::new();
+        loop {
+            // エスケープも制御文字もない部分はまとめてコピーする
+            let start = self.position;
+            while let Some(b) = self.peek() {
+                if b == b'"' || b == b'\\' || b < 0x20 {
+                    break;
+                }
+                self.position += 1;
+            }
+            string.push_str(&self.input[start..self.position]);
+            match self.peek() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(string);
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let c = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.position += 1;
+                            string.push(self.parse_unicode_escape()?);
+                            continue;
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.position += 1;
+                    string.push(c);
+                }
+                Some(_) => return Err(self.error("control character in string")),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    // \uの後の4桁を読む。サロゲートペアは続く\uXXXXと合わせて1文字にする
+    fn parse_unicode_escape(&mut self) -> Result<char, JsonError> {
+        let high = self.parse_hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.bytes[self.position..].starts_with(b"\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.position += 2;
+            let low = self.parse_hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        std::char::from_u32(code).ok_or_else(|| self.error("unpaired surrogate"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self
+            .input
+            .get(self.position..self.position + 4)
+            .filter(|s| s.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.position += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+}
+
+// キーが見つからない行を並べる位置
+// 順序(SortOrder)によらず、先頭か末尾のどちらかにまとめる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingKeys {
+    First,
+    Last,
+}
+
+// 行の並べ替えに使うキー
+// 型の異なる値はnull < false < true < 数値 < 文字列 < 配列とオブジェクトの順に並べ、
+// 配列とオブジェクト同士は等しいとみなす
+#[derive(Debug, Clone, PartialEq)]
+enum Key {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Composite,
+}
+
+impl Key {
+    fn from_value(value: &Value) -> Self {
+        match value {
+            Value::Null => Key::Null,
+            Value::Bool(b) => Key::Bool(*b),
+            Value::Number(n) => Key::Number(*n),
+            Value::String(s) => Key::String(s.clone()),
+            Value::Array(_) | Value::Object(_) => Key::Composite,
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Key::Null => 0,
+            Key::Bool(_) => 1,
+            Key::Number(_) => 2,
+            Key::String(_) => 3,
+            Key::Composite => 4,
+        }
+    }
+
+    fn compare(&self, other: &Key) -> Ordering {
+        match (self, other) {
+            (Key::Bool(a), Key::Bool(b)) => a.cmp(b),
+            // JSONの数値にNaNはない
+            (Key::Number(a), Key::Number(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (Key::String(a), Key::String(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+// 各行をJSONとして解析し、pathの値をキーにして行を並べ替える
+// キーが等しい行は元の順のまま残る
+// 空白だけの行や空行は解析せず、キーのない行と同じようにmissingの位置に並べる
+// 解析できない行があればJsonLinesError::Jsonを返し、linesは変更しない
+// エラーのlineは、linesの中での行番号(1から数える)
+pub fn sort_json_lines(
+    lines: &mut [&str],
+    path: &str,
+    order: &SortOrder,
+    missing: MissingKeys,
+) -> Result<(), JsonLinesError> {
+    // キーは行ごとに1回だけ取り出しておく
+    let mut keyed = Vec::new();
+    keyed
+        .try_reserve_exact(lines.len())
+        .map_err(|_| SortError::AllocationFailed)?;
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            keyed.push((None, i));
+            continue;
+        }
+        let value =
+            parse(line).map_err(|e| JsonLinesError::Json(JsonError { line: i + 1, ..e }))?;
+        keyed.push((value.get_path(path).map(Key::from_value), i));
+    }
+    fourth::sort_by(&mut keyed, &|a, b| {
+        let ordering = match (&a.0, &b.0) {
+            (Some(x), Some(y)) => match *order {
+                SortOrder::Ascending => x.compare(y),
+                SortOrder::Descending => y.compare(x),
+            },
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => match missing {
+                MissingKeys::First => Ordering::Less,
+                MissingKeys::Last => Ordering::Greater,
+            },
+            (Some(_), None) => match missing {
+                MissingKeys::First => Ordering::Greater,
+                MissingKeys::Last => Ordering::Less,
+            },
+        };
+        ordering.then_with(|| a.1.cmp(&b.1))
+    })?;
+    permute_in_place(lines, keyed.into_iter().map(|(_, i)| i).collect());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, sort_json_lines, JsonError, JsonLinesError, MissingKeys, Value};
+    use crate::SortOrder::*;
+
+    #[test]
+    fn parse_values() {
+        let value =
+            parse(r#" {"a": [1, -2.5e2, true, null], "b": {"c": "x\"\u00e9\ud83d\ude00\n"}} "#)
+                .unwrap();
+        assert_eq!(
+            value,
+            Value::Object(vec![
+                (
+                    "a".to_string(),
+                    Value::Array(vec![
+                        Value::Number(1.0),
+                        Value::Number(-250.0),
+                        Value::Bool(true),
+                        Value::Null,
+                    ])
+                ),
+                (
+                    "b".to_string(),
+                    Value::Object(vec![(
+                        "c".to_string(),
+                        Value::String("x\"é😀\n".to_string())
+                    )])
+                ),
+            ])
+        );
+        assert_eq!(
+            value.get_path(".b.c"),
+            Some(&Value::String("x\"é😀\n".to_string()))
+        );
+        assert_eq!(value.get_path("a.1"), Some(&Value::Number(-250.0)));
+        assert_eq!(value.get_path(".a.9"), None);
+        assert_eq!(value.get_path(".b.c.d"), None);
+        assert_eq!(value.get_path("."), Some(&value));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |line, column, message| {
+            Err(JsonError {
+                line,
+                column,
+                message,
+            })
+        };
+        assert_eq!(parse("[1,]"), error(1, 4, "unexpected character"));
+        assert_eq!(parse("{\n  \"a\" 1}"), error(2, 7, "expected ':'"));
+        assert_eq!(
+            parse("01"),
+            error(1, 2, "unexpected characters after the value")
+        );
+        assert_eq!(parse("\"\\ud800\""), error(1, 8, "unpaired surrogate"));
+        assert_eq!(
+            parse("\"a\tb\""),
+            error(1, 3, "control character in string")
+        );
+        assert_eq!(parse("tru"), error(1, 1, "invalid literal"));
+        assert_eq!(parse(""), error(1, 1, "unexpected end of input"));
+        let deep = "[".repeat(1000);
+        assert_eq!(parse(&deep).unwrap_err().message, "too deeply nested");
+    }
+
+    #[test]
+    fn sort_lines_by_nested_key() {
+        let input = [
+            r#"{"request": {"latency_ms": 10}, "id": 1}"#,
+            r#"{"request": {"latency_ms": 9.5}, "id": 2}"#,
+            r#"{"id": 3}"#,
+            r#"{"request": {"latency_ms": 120}, "id": 4}  "#,
+            r#"{"request": {"latency_ms": 9.5}, "id": 5}"#,
+        ];
+        let mut lines = input.to_vec();
+        assert_eq!(
+            sort_json_lines(
+                &mut lines,
+                ".request.latency_ms",
+                &Ascending,
+                MissingKeys::Last
+            ),
+            Ok(())
+        );
+        // 行はそのまま並べ替えられ、末尾の空白も残る
+        assert_eq!(
+            lines,
+            vec![input[1], input[4], input[0], input[3], input[2]]
+        );
+
+        let mut lines = input.to_vec();
+        assert_eq!(
+            sort_json_lines(
+                &mut lines,
+                ".request.latency_ms",
+                &Descending,
+                MissingKeys::First
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            lines,
+            vec![input[2], input[3], input[0], input[1], input[4]]
+        );
+    }
+
+    #[test]
+    fn sort_lines_by_string_and_mixed_types() {
+        let mut lines = vec![
+            r#"{"user": {"name": "bob"}}"#,
+            r#"{"user": {"name": 7}}"#,
+            r#"{"user": {"name": "Alice"}}"#,
+            r#"{"user": {"name": null}}"#,
+            r#"{"user": "anonymous"}"#,
+        ];
+        assert_eq!(
+            sort_json_lines(&mut lines, "user.name", &Ascending, MissingKeys::First),
+            Ok(())
+        );
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"user": "anonymous"}"#,
+                r#"{"user": {"name": null}}"#,
+                r#"{"user": {"name": 7}}"#,
+                r#"{"user": {"name": "Alice"}}"#,
+                r#"{"user": {"name": "bob"}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_blank_lines() {
+        // 空行はエラーにせず、キーのない行と一緒に並べる
+        let mut lines = vec!["{\"a\": 2}", "", "{\"a\": 1}", " \t", "{}"];
+        assert_eq!(
+            sort_json_lines(&mut lines, ".a", &Ascending, MissingKeys::Last),
+            Ok(())
+        );
+        assert_eq!(lines, vec!["{\"a\": 1}", "{\"a\": 2}", "", " \t", "{}"]);
+        assert_eq!(
+            sort_json_lines(&mut lines, ".a", &Descending, MissingKeys::First),
+            Ok(())
+        );
+        assert_eq!(lines, vec!["", " \t", "{}", "{\"a\": 2}", "{\"a\": 1}"]);
+    }
+
+    #[test]
+    fn report_invalid_line() {
+        let mut lines = vec!["{}", "{\"a\": }", "{}"];
+        assert_eq!(
+            sort_json_lines(&mut lines, ".a", &Ascending, MissingKeys::Last),
+            Err(JsonLinesError::Json(JsonError {
+                line: 2,
+                column: 7,
+                message: "unexpected character",
+            }))
+        );
+    }
+}
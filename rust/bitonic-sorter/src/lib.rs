@@ -1,10 +1,86 @@
+pub mod compare;
+pub mod csv;
+pub mod external;
 pub mod first;
+pub mod floats;
 pub mod fourth;
+pub mod iterative;
+pub mod json;
+pub mod network;
+pub mod odd_even;
+pub mod pairs;
+pub mod permutation;
 pub mod second;
+pub mod simd;
+pub mod sorter;
 pub mod third;
+pub mod tuning;
 pub mod utils;
+pub mod zero_one;
+
+use std::{error, fmt};
+
 // 列挙型として昇順、降順を定義する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SortOrder {
     Ascending,
     Descending,
 }
+
+// ソートが失敗した理由を表す列挙型
+// 呼び出し側はメッセージの文字列ではなくバリアントで場合分けできる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SortError {
+    // 2のべき乗の長さしか扱えないソートに、それ以外の長さのスライスが渡された
+    NonPowerOfTwoLength { len: usize },
+    // 比較関数が結果を返せなかった
+    ComparatorFailed,
+    // ソートが途中で中止された
+    Cancelled,
+    // 作業領域のメモリを確保できなかった
+    AllocationFailed,
+    // 要素の位置が、指定された添字の型で表せない
+    IndexOverflow { len: usize },
+    // NaNを許さないソートに、NaNが含まれていた(indexは最初のNaNの位置)
+    NaN { index: usize },
+    // キーと値のスライスの長さが異なる
+    LengthMismatch { keys: usize, values: usize },
+}
+
+// {}で表示したときのメッセージ
+impl fmt::Display for SortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SortError::NonPowerOfTwoLength { len } => write!(
+                f,
+                "The length of x is not a power of two. (x.len(): {})",
+                len
+            ),
+            SortError::ComparatorFailed => write!(f, "The comparator failed."),
+            SortError::Cancelled => write!(f, "The sort was cancelled."),
+            SortError::AllocationFailed => write!(f, "Failed to allocate memory for the sort."),
+            SortError::IndexOverflow { len } => write!(
+                f,
+                "The length of x does not fit in the index type. (x.len(): {})",
+                len
+            ),
+            SortError::NaN { index } => write!(f, "x contains NaN. (index: {})", index),
+            SortError::LengthMismatch { keys, values } => write!(
+                f,
+                "The keys and values have different lengths. (keys.len(): {}, values.len(): {})",
+                keys, values
+            ),
+        }
+    }
+}
+
+// ?演算子やBox<dyn Error>で扱えるようにする
+impl error::Error for SortError {}
+
+// n未満で最大の2のべき乗を返す(nは2以上であること)
+// 例: 8 -> 4, 5 -> 4, 3 -> 2, 2 -> 1
+// 2のべき乗でない長さのスライスを分割する位置として使う
+pub(crate) fn power_of_two_below(n: usize) -> usize {
+    debug_assert!(n > 1);
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
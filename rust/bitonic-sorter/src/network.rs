@@ -0,0 +1,366 @@
+// ソートが実行する比較器のネットワークを取り出して、JSON、Graphviz DOT、SVGで出力する
+// バイトニックソートは入力の値によらず同じ組を比較するので、
+// 位置を要素とするスライスを「決して交換しない比較関数」でソートすると、
+// 比較関数に渡される2つの値がそのまま比較器の位置の組になる
+
+use super::third;
+use super::SortOrder;
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+// 比較器1つ分
+// i < jで、directionがAscendingならiに前に並ぶ方、jに後ろに並ぶ方が入る
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Comparator {
+    pub i: usize,
+    pub j: usize,
+    pub direction: SortOrder,
+}
+
+// 比較器のネットワーク
+// comparatorsは実行順、layersは同時に実行できる比較器ごとにまとめたもの
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    len: usize,
+    comparators: Vec<Comparator>,
+    layers: Vec<Vec<Comparator>>,
+}
+
+// recordがソート関数に渡す比較関数の型
+// Box<dyn Fn>もFnを実装しているので、third::sort_byなどにそのまま渡せる
+pub type Recorder<'a> = Box<dyn Fn(&usize, &usize) -> Ordering + Sync + 'a>;
+
+impl Network {
+    // third::sort_byが長さlenのスライスに対して実行するネットワーク
+    pub fn bitonic(len: usize) -> Self {
+        Self::record(len, |x, comparator| {
+            third::sort_by(x, comparator).expect("third::sort_by accepts any length");
+        })
+    }
+
+    // sort_byが呼び出す比較を記録してネットワークを作る
+    // sort_byは受け取った比較関数だけを使って、スライスをソートしなければならない
+    pub fn record<S>(len: usize, sort_by: S) -> Self
+    where
+        S: FnOnce(&mut [usize], &Recorder),
+    {
+        let pairs = Mutex::new(Vec::new());
+        {
+            let recorder: Recorder = Box::new(|a, b| {
+                pairs.lock().unwrap().push((*a, *b));
+                // Equalを返すと交換されないので、要素の値はいつまでも位置と等しい
+                Ordering::Equal
+            });
+            let mut x: Vec<usize> = (0..len).collect();
+            sort_by(&mut x, &recorder);
+        }
+        // 比較関数が第1引数を前に並べたいとき、その要素はaの位置に残る
+        let comparators = pairs
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|(a, b)| Comparator {
+                i: a.min(b),
+                j: a.max(b),
+                direction: if a < b {
+                    SortOrder::Ascending
+                } else {
+                    SortOrder::Descending
+                },
+            })
+            .collect();
+        Self::from_comparators(len, comparators)
+    }
+
+    // 実行順に並んだ比較器からネットワークを作る
+    // 各比較器は、その2本の線の直前の比較器より1つ後の層に入れる
+    // 層の中の比較器は実行順によらないように、位置の順に並べる
+    pub fn from_comparators(len: usize, comparators: Vec<Comparator>) -> Self {
+        let mut depth = vec![0; len];
+        let mut layers: Vec<Vec<Comparator>> = Vec::new();
+        for c in comparators.iter() {
+            let layer = depth[c.i].max(depth[c.j]);
+            if layer == layers.len() {
+                layers.push(Vec::new());
+            }
+            layers[layer].push(*c);
+            depth[c.i] = layer + 1;
+            depth[c.j] = layer + 1;
+        }
+        for layer in layers.iter_mut() {
+            layer.sort_by_key(|c| (c.i, c.j));
+        }
+        Self {
+            len,
+            comparators,
+            layers,
+        }
+    }
+
+    // 入力の長さ(線の本数)
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn comparators(&self) -> &[Comparator] {
+        &self.comparators
+    }
+
+    pub fn layers(&self) -> &[Vec<Comparator>] {
+        &self.layers
+    }
+
+    // 例: {"len":2,"depth":1,"comparators":[{"i":0,"j":1,"direction":"ascending","layer":0}]}
+    // 比較器は実行順に並び、layerで層が分かる
+    pub fn to_json(&self) -> String {
+        let mut items = Vec::with_capacity(self.comparators.len());
+        let mut depth = vec![0; self.len];
+        for c in self.comparators.iter() {
+            let layer = depth[c.i].max(depth[c.j]);
+            depth[c.i] = layer + 1;
+            depth[c.j] = layer + 1;
+            items.push(format!(
+                r#"{{"i":{},"j":{},"direction":"{}","layer":{}}}"#,
+                c.i,
+                c.j,
+                direction_name(c.direction),
+                layer
+            ));
+        }
+        format!(
+            r#"{{"len":{},"depth":{},"comparators":[{}]}}"#,
+            self.len,
+            self.layers.len(),
+            items.join(",")
+        )
+    }
+
+    // 線の各層の位置を点、比較器を縦の辺として表す
+    // w{線}_{層}は、その層の比較器を通る直前の線上の点
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("graph network {\n");
+        dot.push_str("    rankdir=LR;\n");
+        dot.push_str("    node [shape=point];\n");
+        for layer in 0..=self.layers.len() {
+            let nodes: Vec<String> = (0..self.len).map(|w| format!("w{}_{}", w, layer)).collect();
+            writeln!(dot, "    {{ rank=same; {}; }}", nodes.join("; ")).unwrap();
+        }
+        // 線を左から右に横に結ぶ
+        for w in 0..self.len {
+            let nodes: Vec<String> = (0..=self.layers.len())
+                .map(|layer| format!("w{}_{}", w, layer))
+                .collect();
+            writeln!(dot, "    {} [weight=100];", nodes.join(" -- ")).unwrap();
+        }
+        for (layer, comparators) in self.layers.iter().enumerate() {
+            for c in comparators.iter() {
+                writeln!(
+                    dot,
+                    "    w{}_{} -- w{}_{} [constraint=false, label=\"{}\"];",
+                    c.i,
+                    layer,
+                    c.j,
+                    layer,
+                    direction_name(c.direction)
+                )
+                .unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // Knuthの本と同じ描き方で、線を横に、比較器を両端に点のある縦線で描く
+    // 同じ層で範囲が重なる比較器は、少しずつ右にずらして描く
+    // Descendingの比較器は赤で描く
+    pub fn to_svg(&self) -> String {
+        const WIRE_GAP: usize = 20;
+        const COLUMN_GAP: usize = 12;
+        const LAYER_GAP: usize = 16;
+        const MARGIN: usize = 20;
+
+        // 層ごとに比較器を描く列を決める
+        let mut placed = Vec::new();
+        let mut x = MARGIN + LAYER_GAP;
+        for comparators in self.layers.iter() {
+            let mut columns: Vec<Vec<(usize, usize)>> = Vec::new();
+            for c in comparators.iter() {
+                let column = columns
+                    .iter()
+                    .position(|used| used.iter().all(|&(lo, hi)| c.j < lo || hi < c.i))
+                    .unwrap_or_else(|| {
+                        columns.push(Vec::new());
+                        columns.len() - 1
+                    });
+                columns[column].push((c.i, c.j));
+                placed.push((x + column * COLUMN_GAP, *c));
+            }
+            x += columns.len().max(1) * COLUMN_GAP + LAYER_GAP;
+        }
+        let width = x + MARGIN;
+        let height = MARGIN * 2 + self.len.saturating_sub(1) * WIRE_GAP;
+        let y = |w: usize| MARGIN + w * WIRE_GAP;
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+            width, height, width, height
+        )
+        .unwrap();
+        writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#).unwrap();
+        for w in 0..self.len {
+            writeln!(
+                svg,
+                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="black"/>"#,
+                MARGIN,
+                y(w),
+                width - MARGIN,
+                y(w)
+            )
+            .unwrap();
+        }
+        for (x, c) in placed {
+            let color = match c.direction {
+                SortOrder::Ascending => "black",
+                SortOrder::Descending => "red",
+            };
+            writeln!(
+                svg,
+                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}" stroke-width="2"/>"#,
+                x,
+                y(c.i),
+                x,
+                y(c.j),
+                color
+            )
+            .unwrap();
+            for &w in [c.i, c.j].iter() {
+                writeln!(
+                    svg,
+                    r#"<circle cx="{}" cy="{}" r="3" fill="{}"/>"#,
+                    x,
+                    y(w),
+                    color
+                )
+                .unwrap();
+            }
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+fn direction_name(direction: SortOrder) -> &'static str {
+    match direction {
+        SortOrder::Ascending => "ascending",
+        SortOrder::Descending => "descending",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Comparator, Network};
+    use crate::SortOrder::*;
+    use crate::{fourth, iterative};
+
+    fn comparator(i: usize, j: usize) -> Comparator {
+        Comparator {
+            i,
+            j,
+            direction: Ascending,
+        }
+    }
+
+    #[test]
+    fn bitonic_network_of_four() {
+        let network = Network::bitonic(4);
+        assert_eq!(network.len(), 4);
+        assert_eq!(
+            network.comparators(),
+            &[
+                comparator(0, 1),
+                comparator(2, 3),
+                comparator(1, 2),
+                comparator(0, 3),
+                comparator(0, 1),
+                comparator(2, 3),
+            ]
+        );
+        assert_eq!(
+            network.layers(),
+            &[
+                vec![comparator(0, 1), comparator(2, 3)],
+                vec![comparator(0, 3), comparator(1, 2)],
+                vec![comparator(0, 1), comparator(2, 3)],
+            ]
+        );
+    }
+
+    #[test]
+    fn bitonic_network_depth() {
+        // 2^kの長さではk(k + 1) / 2層になる
+        assert_eq!(Network::bitonic(8).layers().len(), 6);
+        assert_eq!(Network::bitonic(16).layers().len(), 10);
+        assert_eq!(Network::bitonic(1).layers().len(), 0);
+    }
+
+    #[test]
+    fn other_sorters_execute_the_same_network() {
+        for len in 0..=33 {
+            let expected = Network::bitonic(len);
+            let par = Network::record(len, |x, c| fourth::sort_by(x, c).unwrap());
+            assert_eq!(par.layers(), expected.layers(), "len: {}", len);
+            let iter = Network::record(len, |x, c| iterative::sort_by(x, c).unwrap());
+            assert_eq!(iter.layers(), expected.layers(), "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn record_descending_comparators() {
+        // 後ろの位置を前に並べたい比較はDescendingとして記録される
+        let network = Network::record(3, |x, c| {
+            c(&x[2], &x[0]);
+        });
+        assert_eq!(
+            network.comparators(),
+            &[Comparator {
+                i: 0,
+                j: 2,
+                direction: Descending
+            }]
+        );
+    }
+
+    #[test]
+    fn network_to_json() {
+        assert_eq!(
+            Network::bitonic(3).to_json(),
+            concat!(
+                r#"{"len":3,"depth":3,"comparators":["#,
+                r#"{"i":0,"j":1,"direction":"ascending","layer":0},"#,
+                r#"{"i":1,"j":2,"direction":"ascending","layer":1},"#,
+                r#"{"i":0,"j":1,"direction":"ascending","layer":2}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn network_to_dot_and_svg() {
+        let network = Network::bitonic(4);
+        let dot = network.to_dot();
+        assert!(dot.starts_with("graph network {"));
+        assert!(dot.contains("w0_1 -- w3_1 [constraint=false, label=\"ascending\"];"));
+        let svg = network.to_svg();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        // 比較器1つにつき縦線1本と点2つ
+        assert_eq!(svg.matches("<circle").count(), 12);
+    }
+}
@@ -0,0 +1,246 @@
+// Batcherの奇偶マージソート
+// バイトニックソートと同じく、入力の値によらず決まった組を比較する比較器のネットワークで、
+// 長さ2^kのときの比較器の数はバイトニックソートの(k^2 + k) * 2^(k - 2)に対して
+// (k^2 - k + 4) * 2^(k - 2) - 1と少ない
+// 長さが2のべき乗でないときは、後ろに最大の要素を補った列のネットワークから
+// 実在する要素同士の比較器だけを残す
+// 補った要素は昇順の比較器では動かないので、残りの比較器だけで正しくソートできる
+
+use super::sorter::Sorter;
+use super::{SortError, SortOrder};
+use rayon::prelude::*;
+use std::cmp::Ordering;
+
+// 順次版
+pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    run_stages(x.len(), |merge_len, distance| {
+        let start = distance % merge_len;
+        for (i, chunk) in x[start..].chunks_mut(distance * 2).enumerate() {
+            stage(
+                chunk,
+                start + i * distance * 2,
+                merge_len,
+                distance,
+                comparator,
+            );
+        }
+    });
+    Ok(())
+}
+
+// 並列版
+// 1つの段の比較器は互いに異なる要素を比較するので、段の中をrayonで分担する
+pub fn par_sort<T: Ord + Send>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => par_sort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => par_sort_by(x, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn par_sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+where
+    T: Send,
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    // 並列に処理する閾値は既定の設定のSorterと同じ値を使う
+    let threshold = Sorter::new().resolve::<T>().get_parallel_threshold();
+    run_stages(x.len(), |merge_len, distance| {
+        let start = distance % merge_len;
+        let chunk_len = distance * 2;
+        if chunk_len >= threshold {
+            // 塊が大きい上位の段では、塊の中の比較も並列に行う
+            x[start..]
+                .par_chunks_mut(chunk_len)
+                .enumerate()
+                .for_each(|(i, chunk)| {
+                    par_stage(
+                        chunk,
+                        start + i * chunk_len,
+                        merge_len,
+                        distance,
+                        comparator,
+                        threshold,
+                    )
+                });
+        } else {
+            // 塊が小さい段では、複数の塊をまとめて1つのタスクにする
+            x[start..]
+                .par_chunks_mut(chunk_len)
+                .with_min_len(threshold / chunk_len)
+                .enumerate()
+                .for_each(|(i, chunk)| {
+                    stage(
+                        chunk,
+                        start + i * chunk_len,
+                        merge_len,
+                        distance,
+                        comparator,
+                    )
+                });
+        }
+    });
+    Ok(())
+}
+
+// 奇偶マージソートの段を順番に呼び出す
+// merge_lenはマージしている2つのソート済みの列それぞれの長さp、
+// distanceは比較する2要素の距離k
+fn run_stages<S>(len: usize, mut stage: S)
+where
+    S: FnMut(usize, usize),
+{
+    let mut merge_len = 1;
+    while merge_len < len {
+        let mut distance = merge_len;
+        while distance > 0 {
+            stage(merge_len, distance);
+            distance /= 2;
+        }
+        merge_len *= 2;
+    }
+}
+
+// 1つの塊に対して1段分の比較交換を行う
+// chunk_startは塊の先頭の位置
+// 2つの要素が同じマージの範囲(長さ2 * merge_len)に入っているときだけ比較する
+fn stage<T, F>(
+    chunk: &mut [T],
+    chunk_start: usize,
+    merge_len: usize,
+    distance: usize,
+    comparator: &F,
+) where
+    F: Fn(&T, &T) -> Ordering,
+{
+    if chunk.len() <= distance {
+        return;
+    }
+    let (first, second) = chunk.split_at_mut(distance);
+    for (i, (a, b)) in first.iter_mut().zip(second.iter_mut()).enumerate() {
+        if same_merge(chunk_start + i, distance, merge_len) {
+            compare_and_swap(a, b, comparator);
+        }
+    }
+}
+
+fn par_stage<T, F>(
+    chunk: &mut [T],
+    chunk_start: usize,
+    merge_len: usize,
+    distance: usize,
+    comparator: &F,
+    threshold: usize,
+) where
+    T: Send,
+    F: Sync + Fn(&T, &T) -> Ordering,
+{
+    if chunk.len() <= distance {
+        return;
+    }
+    let (first, second) = chunk.split_at_mut(distance);
+    first
+        .par_iter_mut()
+        .zip(second.par_iter_mut())
+        .enumerate()
+        .with_min_len(threshold)
+        .filter(|(i, _)| same_merge(chunk_start + i, distance, merge_len))
+        .for_each(|(_, (a, b))| compare_and_swap(a, b, comparator));
+}
+
+// 位置iとi + distanceの要素が、同じマージの範囲に入っているか調べる
+fn same_merge(i: usize, distance: usize, merge_len: usize) -> bool {
+    i / (merge_len * 2) == (i + distance) / (merge_len * 2)
+}
+
+// aがbより後ろに並ぶべきなら交換する
+fn compare_and_swap<T, F>(a: &mut T, b: &mut T, comparator: &F)
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    if comparator(a, b) == Ordering::Greater {
+        std::mem::swap(a, b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{par_sort, par_sort_by, sort, sort_by};
+    use crate::network::Network;
+    use crate::utils::{is_sorted_ascending, is_sorted_descending, new_u32_vec};
+    use crate::zero_one::verify;
+    use crate::SortOrder::*;
+
+    #[test]
+    fn sort_u32_ascending_and_descending() {
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![4, 10, 11, 20, 21, 30, 110, 330]);
+
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(par_sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 110, 30, 21, 20, 11, 10, 4]);
+    }
+
+    #[test]
+    fn sort_u32_large_and_odd_lengths() {
+        for &len in [0, 1, 2, 3, 13, 33, 1021, 8193, 65536].iter() {
+            let mut x = new_u32_vec(len);
+            assert_eq!(sort(&mut x, &Ascending), Ok(()));
+            assert!(is_sorted_ascending(&x), "len: {}", len);
+
+            let mut x = new_u32_vec(len);
+            assert_eq!(par_sort(&mut x, &Descending), Ok(()));
+            assert!(is_sorted_descending(&x), "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn same_result_in_parallel() {
+        // 上位16bitだけで比較するので、キーが等しい要素が多数ある
+        let comparator = |a: &u32, b: &u32| (a >> 16).cmp(&(b >> 16));
+        for &len in [5, 100, 4097, 20000].iter() {
+            let x: Vec<u32> = new_u32_vec(len).into_iter().map(|v| v >> 8).collect();
+            let mut seq = x.clone();
+            assert_eq!(sort_by(&mut seq, &comparator), Ok(()));
+            let mut par = x;
+            assert_eq!(par_sort_by(&mut par, &comparator), Ok(()));
+            assert_eq!(par, seq, "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn zero_one_principle_up_to_20() {
+        for len in 0..=20 {
+            let network = Network::record(len, |x, c| sort_by(x, c).unwrap());
+            assert_eq!(verify(&network), Ok(()), "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn fewer_comparators_than_bitonic() {
+        // 長さ2^kでは(k^2 - k + 4) * 2^(k - 2) - 1個
+        for &(len, expected) in [(2, 1), (4, 5), (8, 19), (16, 63), (1024, 24063)].iter() {
+            let network = Network::record(len, |x, c| sort_by(x, c).unwrap());
+            assert_eq!(network.comparators().len(), expected, "len: {}", len);
+        }
+        for len in 5..=64 {
+            let odd_even = Network::record(len, |x, c| sort_by(x, c).unwrap());
+            let bitonic = Network::bitonic(len);
+            assert!(
+                odd_even.comparators().len() < bitonic.comparators().len(),
+                "len: {}",
+                len
+            );
+        }
+    }
+}
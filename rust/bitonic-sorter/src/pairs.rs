@@ -0,0 +1,109 @@
+// キーと値を別々のスライスで持つデータのソート
+// fourthと同じネットワークをキーだけで実行し、キーを交換するたびに同じ位置の値も交換する
+// (キー, 値)のタプルのベクタを作らずにソートできる
+// どんな長さでもソートでき、キーが等しい要素の順序は保たれない
+//
+// 閾値や小さなブロックのソート方法を変えたいときは、Sorter::sort_pairsを使う
+// u32のキーとu32の値なら、AVX2で比較交換するsimd::sort_pairs_u32も使える
+
+use super::sorter::Sorter;
+use super::{SortError, SortOrder};
+
+// 順次版
+pub fn sort_pairs<K: Ord + Send, V: Send>(
+    keys: &mut [K],
+    values: &mut [V],
+    order: &SortOrder,
+) -> Result<(), SortError> {
+    // 閾値を最大にすると、rayonでタスクを分けずに順番に処理する
+    Sorter::new()
+        .parallel_threshold(usize::MAX)
+        .sort_pairs(keys, values, order)
+}
+
+// 既定の設定のSorterで並列にソートする
+pub fn par_sort_pairs<K: Ord + Send, V: Send>(
+    keys: &mut [K],
+    values: &mut [V],
+    order: &SortOrder,
+) -> Result<(), SortError> {
+    Sorter::new().sort_pairs(keys, values, order)
+}
+
+pub(crate) fn check_lengths<K, V>(keys: &[K], values: &[V]) -> Result<(), SortError> {
+    if keys.len() == values.len() {
+        Ok(())
+    } else {
+        Err(SortError::LengthMismatch {
+            keys: keys.len(),
+            values: values.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{par_sort_pairs, sort_pairs};
+    use crate::utils::{is_sorted_ascending, is_sorted_descending, new_u32_vec};
+    use crate::SortError;
+    use crate::SortOrder::*;
+
+    #[test]
+    fn sort_pairs_carries_values() {
+        let mut keys = vec![10, 30, 11, 20, 4, 330, 21];
+        let mut values = vec!["ten", "thirty", "eleven", "twenty", "four", "330", "21"];
+        assert_eq!(sort_pairs(&mut keys, &mut values, &Ascending), Ok(()));
+        assert_eq!(keys, vec![4, 10, 11, 20, 21, 30, 330]);
+        assert_eq!(
+            values,
+            vec!["four", "ten", "eleven", "twenty", "21", "thirty", "330"]
+        );
+
+        assert_eq!(par_sort_pairs(&mut keys, &mut values, &Descending), Ok(()));
+        assert_eq!(keys, vec![330, 30, 21, 20, 11, 10, 4]);
+        assert_eq!(
+            values,
+            vec!["330", "thirty", "21", "twenty", "eleven", "ten", "four"]
+        );
+    }
+
+    #[test]
+    fn sort_pairs_length_mismatch() {
+        let mut keys = vec![3, 2, 1];
+        let mut values = vec![1, 2];
+        assert_eq!(
+            sort_pairs(&mut keys, &mut values, &Ascending),
+            Err(SortError::LengthMismatch { keys: 3, values: 2 })
+        );
+        assert_eq!(keys, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn par_sort_pairs_matches_sequential() {
+        // 値には元の位置を入れて、キーと値の組が崩れていないことを確かめる
+        for &len in [0, 1, 2, 1000, 4096, 10_001].iter() {
+            let original = new_u32_vec(len);
+            let mut keys = original.clone();
+            let mut values: Vec<usize> = (0..len).collect();
+            assert_eq!(sort_pairs(&mut keys, &mut values, &Ascending), Ok(()));
+            assert!(is_sorted_ascending(&keys));
+            assert!(keys.iter().zip(&values).all(|(&k, &i)| original[i] == k));
+            let mut seen = values.clone();
+            seen.sort();
+            assert_eq!(seen, (0..len).collect::<Vec<_>>());
+
+            let mut par_keys = original.clone();
+            let mut par_values: Vec<usize> = (0..len).collect();
+            assert_eq!(
+                par_sort_pairs(&mut par_keys, &mut par_values, &Ascending),
+                Ok(())
+            );
+            assert_eq!((par_keys, par_values), (keys, values), "len: {}", len);
+
+            let mut keys = original;
+            let mut values: Vec<usize> = (0..len).collect();
+            assert_eq!(par_sort_pairs(&mut keys, &mut values, &Descending), Ok(()));
+            assert!(is_sorted_descending(&keys));
+        }
+    }
+}
@@ -0,0 +1,177 @@
+// argsortが返す並べ替え(置換)を扱うための型と関数
+// 置換indicesは「p番目に来るべき要素の元の位置がindices[p]」を表す
+
+use super::SortError;
+
+// 置換の添字に使える整数型
+// 要素数が少ないときはu32にすると、usizeの半分のメモリで済む
+pub trait SortIndex: Copy + Ord + Send + Sync {
+    // iがこの型で表せなければNoneを返す
+    fn from_usize(i: usize) -> Option<Self>;
+    fn to_usize(self) -> usize;
+}
+
+impl SortIndex for usize {
+    fn from_usize(i: usize) -> Option<Self> {
+        Some(i)
+    }
+
+    fn to_usize(self) -> usize {
+        self
+    }
+}
+
+impl SortIndex for u32 {
+    fn from_usize(i: usize) -> Option<Self> {
+        if i <= u32::MAX as usize {
+            Some(i as u32)
+        } else {
+            None
+        }
+    }
+
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+}
+
+// apply_permutationで並べ替えられる列
+// Vec<T>と&mut [T]に実装してあるので、型の違う列をまとめて渡せる
+pub trait Permute {
+    fn element_count(&self) -> usize;
+    fn swap_elements(&mut self, a: usize, b: usize);
+}
+
+impl<T> Permute for Vec<T> {
+    fn element_count(&self) -> usize {
+        self.len()
+    }
+
+    fn swap_elements(&mut self, a: usize, b: usize) {
+        self.as_mut_slice().swap(a, b)
+    }
+}
+
+impl<T> Permute for &mut [T] {
+    fn element_count(&self) -> usize {
+        self.len()
+    }
+
+    fn swap_elements(&mut self, a: usize, b: usize) {
+        <[T]>::swap(self, a, b)
+    }
+}
+
+// 置換indicesを、同じ長さの列すべてにその場で適用する
+// 例: let order = third::argsort(&keys, &Ascending)?;
+//     apply_permutation(&order, &mut [&mut keys, &mut names, &mut ages]);
+// indicesが置換になっていない場合や、列の長さが異なる場合はパニックする
+pub fn apply_permutation<I: SortIndex>(indices: &[I], columns: &mut [&mut dyn Permute]) {
+    let len = indices.len();
+    for column in columns.iter() {
+        assert_eq!(
+            column.element_count(),
+            len,
+            "all columns must have the same length as the permutation"
+        );
+    }
+    let mut seen = vec![false; len];
+    for &i in indices.iter() {
+        let i = i.to_usize();
+        assert!(i < len && !seen[i], "indices is not a permutation");
+        seen[i] = true;
+    }
+    // 巡回置換ごとにswapでたどる
+    // seenを処理済みの印として使い回す
+    for start in 0..len {
+        if !seen[start] {
+            continue;
+        }
+        let mut p = start;
+        loop {
+            seen[p] = false;
+            let src = indices[p].to_usize();
+            if src == start {
+                break;
+            }
+            for column in columns.iter_mut() {
+                column.swap_elements(p, src);
+            }
+            p = src;
+        }
+    }
+}
+
+// 0, 1, ..., len - 1を並べた、何も並べ替えない置換を作る
+pub(crate) fn identity<I: SortIndex>(len: usize) -> Result<Vec<I>, SortError> {
+    if len > 0 && I::from_usize(len - 1).is_none() {
+        return Err(SortError::IndexOverflow { len });
+    }
+    let mut indices = Vec::new();
+    indices
+        .try_reserve_exact(len)
+        .map_err(|_| SortError::AllocationFailed)?;
+    indices.extend((0..len).map(|i| I::from_usize(i).unwrap()));
+    Ok(indices)
+}
+
+// 1つの列に置換を適用する
+// indicesは作業用に書き換えるので、所有権ごと受け取る
+pub(crate) fn permute_in_place<T, I: SortIndex>(x: &mut [T], mut indices: Vec<I>) {
+    for start in 0..x.len() {
+        let mut p = start;
+        while indices[p].to_usize() != p {
+            let src = indices[p].to_usize();
+            // 処理済みの位置はindices[p] = pにして印を付ける
+            indices[p] = I::from_usize(p).unwrap();
+            if src == start {
+                break;
+            }
+            x.swap(p, src);
+            p = src;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_permutation, permute_in_place};
+
+    #[test]
+    fn apply_to_parallel_columns() {
+        let mut ids: Vec<u32> = vec![30, 10, 40, 20];
+        let mut names = vec!["c", "a", "d", "b"];
+        let mut scores = vec![3.0, 1.0, 4.0, 2.0];
+        let mut slice_storage = [3u8, 1, 4, 2];
+        let mut slice = &mut slice_storage[..];
+        let order: Vec<u32> = vec![1, 3, 0, 2];
+        apply_permutation(&order, &mut [&mut ids, &mut names, &mut scores, &mut slice]);
+        assert_eq!(ids, vec![10, 20, 30, 40]);
+        assert_eq!(names, vec!["a", "b", "c", "d"]);
+        assert_eq!(scores, vec![1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(slice_storage, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn permute_single_slice() {
+        // 長い巡回置換と不動点が混ざった置換
+        let mut x = vec!['a', 'b', 'c', 'd', 'e', 'f'];
+        permute_in_place(&mut x, vec![4usize, 0, 2, 1, 5, 3]);
+        assert_eq!(x, vec!['e', 'a', 'c', 'b', 'f', 'd']);
+    }
+
+    #[test]
+    #[should_panic(expected = "indices is not a permutation")]
+    fn reject_non_permutation() {
+        let mut x = vec![1, 2, 3];
+        apply_permutation(&[0usize, 0, 1], &mut [&mut x]);
+    }
+
+    #[test]
+    #[should_panic(expected = "same length")]
+    fn reject_length_mismatch() {
+        let mut x = vec![1, 2, 3];
+        let mut y = vec![1, 2];
+        apply_permutation(&[0usize, 1, 2], &mut [&mut x, &mut y]);
+    }
+}
@@ -9,10 +9,10 @@
 // Rustでは関数、変数、定数にスネークケース、
 // ユーザが定義した型やジェネリクス型パラメータの識別子にはキャメルケースを用いる
 
-use super::SortOrder;
+use super::{SortError, SortOrder};
 
 // match式による場合分けをしてdo_sort()に渡す
-pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
+pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
     if x.len().is_power_of_two() {
         match *order {
             SortOrder::Ascending => do_sort(x, true),
@@ -20,10 +20,7 @@ pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
         };
         Ok(())
     } else {
-        Err(format!(
-            "The length of x is not a power of two. (x.len(): {})",
-            x.len()
-        ))
+        Err(SortError::NonPowerOfTwoLength { len: x.len() })
     }
 }
 
@@ -65,6 +62,7 @@ fn compare_and_swap<T: Ord>(x: &mut [T], up: bool) {
 mod tests {
     // 親モジュール(first)のsort関数を使用する
     use super::sort;
+    use crate::SortError;
     use crate::SortOrder::*;
 
     // テストケースになる関数は#[test]アトリビュートを付ける
@@ -153,4 +151,16 @@ mod tests {
         let mut x = vec![10, 30, 11]; // 2のべき乗でない
         assert!(sort(&mut x, &Ascending).is_err()); // 戻り値はErr
     }
+
+    #[test]
+    fn sort_to_fail_with_len() {
+        let mut x = vec![10, 30, 11, 20, 4];
+        // 失敗の理由はバリアントで判別できる
+        let err = sort(&mut x, &Ascending).unwrap_err();
+        assert_eq!(err, SortError::NonPowerOfTwoLength { len: 5 });
+        assert_eq!(
+            err.to_string(),
+            "The length of x is not a power of two. (x.len(): 5)"
+        );
+    }
 }
@@ -0,0 +1,430 @@
+// u32とi32に特化したバイトニックソート
+// 比較交換をAVX2のmin/max命令で8要素ずつまとめて行う
+// AVX2が使えるかは実行時に判定し、使えないCPUではスカラー版で比較交換する
+// 再帰と並列化はfourthのネットワークをそのまま使い、
+// 前半と後半の比較交換だけをこのモジュールの実装に置き換える
+
+use super::fourth::{self, Columns, Exchange};
+use super::pairs;
+use super::sorter::Sorter;
+use super::{SortError, SortOrder};
+use std::cmp::Ordering;
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+// u32のスライスをソートする
+pub fn sort_u32(x: &mut [u32], order: &SortOrder) -> Result<(), SortError> {
+    sort(x, order)
+}
+
+// i32のスライスをソートする
+pub fn sort_i32(x: &mut [i32], order: &SortOrder) -> Result<(), SortError> {
+    sort(x, order)
+}
+
+// u32のキーとu32の値の組をソートする
+// pairs::par_sort_pairsと同じネットワークと交換の条件で、比較交換をAVX2で8組ずつ行う
+pub fn sort_pairs_u32(
+    keys: &mut [u32],
+    values: &mut [u32],
+    order: &SortOrder,
+) -> Result<(), SortError> {
+    pairs::check_lengths(keys, values)?;
+    let config = Sorter::new().resolve::<u32>();
+    let forward = *order == SortOrder::Ascending;
+    fourth::do_sort(Columns::new(keys, values), forward, &lanes(), &config);
+    Ok(())
+}
+
+// 閾値は既定の設定のSorterと同じ値を使う
+fn sort<T: Lane>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    let config = Sorter::new().resolve::<T>();
+    let forward = *order == SortOrder::Ascending;
+    fourth::do_sort(Columns::keys(x), forward, &lanes(), &config);
+    Ok(())
+}
+
+// CPUの機能の判定はソートの開始時に一度だけ行う
+fn lanes() -> Lanes {
+    Lanes { avx2: has_avx2() }
+}
+
+// 実行中のCPUがAVX2に対応しているか調べる
+fn has_avx2() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("avx2")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+// fourthのネットワークに渡す比較交換の実装
+// avx2がtrueになるのはhas_avx2()で対応を確認したときだけ
+struct Lanes {
+    avx2: bool,
+}
+
+impl<T: Lane> Exchange<T> for Lanes {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn compare_and_swap(&self, first: Columns<T, ()>, second: Columns<T, ()>, forward: bool) {
+        T::compare_and_swap(first.keys, second.keys, forward, self.avx2)
+    }
+
+    fn flip_and_swap(&self, first: Columns<T, ()>, second: Columns<T, ()>, forward: bool) {
+        T::flip_and_swap(first.keys, second.keys, forward, self.avx2)
+    }
+}
+
+// キーと値の組では、キーを比較したマスクでキーと値を同じように入れ替える
+// AVX2が使えないときは、1組ずつ比較交換する
+impl Exchange<u32, u32> for Lanes {
+    fn compare(&self, a: &u32, b: &u32) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn compare_and_swap(&self, first: Columns<u32, u32>, second: Columns<u32, u32>, forward: bool) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if self.avx2 {
+                unsafe { compare_and_swap_pairs_avx2(first, second, forward) };
+                return;
+            }
+        }
+        scalar_pairs().compare_and_swap(first, second, forward)
+    }
+
+    fn flip_and_swap(&self, first: Columns<u32, u32>, second: Columns<u32, u32>, forward: bool) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if self.avx2 {
+                unsafe { flip_and_swap_pairs_avx2(first, second, forward) };
+                return;
+            }
+        }
+        scalar_pairs().flip_and_swap(first, second, forward)
+    }
+}
+
+// 1組ずつ比較交換するExchange
+fn scalar_pairs() -> impl Exchange<u32, u32> {
+    |a: &u32, b: &u32| a.cmp(b)
+}
+
+// 交換する組のレーンがすべて1になるマスクを返す
+// AVX2には符号なしの大小比較がないので、符号ビットを反転させて符号付きで比較する
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn swap_mask(a: __m256i, b: __m256i, forward: bool) -> __m256i {
+    let sign = _mm256_set1_epi32(i32::MIN);
+    let (a, b) = (_mm256_xor_si256(a, sign), _mm256_xor_si256(b, sign));
+    if forward {
+        _mm256_cmpgt_epi32(a, b)
+    } else {
+        _mm256_cmpgt_epi32(b, a)
+    }
+}
+
+// キーを比較して作ったマスクで、キーと値を同じように入れ替える
+// 8組に満たない残りは1組ずつ比較交換する
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn compare_and_swap_pairs_avx2(
+    first: Columns<u32, u32>,
+    second: Columns<u32, u32>,
+    forward: bool,
+) {
+    let len = second.keys.len();
+    let mut i = 0;
+    while i + 8 <= len {
+        let ka_ptr = first.keys.as_mut_ptr().add(i) as *mut __m256i;
+        let kb_ptr = second.keys.as_mut_ptr().add(i) as *mut __m256i;
+        let va_ptr = first.values.as_mut_ptr().add(i) as *mut __m256i;
+        let vb_ptr = second.values.as_mut_ptr().add(i) as *mut __m256i;
+        let (ka, kb) = (_mm256_loadu_si256(ka_ptr), _mm256_loadu_si256(kb_ptr));
+        let (va, vb) = (_mm256_loadu_si256(va_ptr), _mm256_loadu_si256(vb_ptr));
+        let swap = swap_mask(ka, kb, forward);
+        _mm256_storeu_si256(ka_ptr, _mm256_blendv_epi8(ka, kb, swap));
+        _mm256_storeu_si256(kb_ptr, _mm256_blendv_epi8(kb, ka, swap));
+        _mm256_storeu_si256(va_ptr, _mm256_blendv_epi8(va, vb, swap));
+        _mm256_storeu_si256(vb_ptr, _mm256_blendv_epi8(vb, va, swap));
+        i += 8;
+    }
+    scalar_pairs().compare_and_swap(
+        Columns::new(&mut first.keys[i..len], &mut first.values[i..len]),
+        Columns::new(&mut second.keys[i..], &mut second.values[i..]),
+        forward,
+    );
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn flip_and_swap_pairs_avx2(
+    first: Columns<u32, u32>,
+    second: Columns<u32, u32>,
+    forward: bool,
+) {
+    let len = second.keys.len();
+    let mid_point = first.keys.len();
+    let reverse = _mm256_setr_epi32(7, 6, 5, 4, 3, 2, 1, 0);
+    let mut i = 0;
+    while i + 8 <= len {
+        let ka_ptr = first.keys.as_mut_ptr().add(mid_point - 8 - i) as *mut __m256i;
+        let kb_ptr = second.keys.as_mut_ptr().add(i) as *mut __m256i;
+        let va_ptr = first.values.as_mut_ptr().add(mid_point - 8 - i) as *mut __m256i;
+        let vb_ptr = second.values.as_mut_ptr().add(i) as *mut __m256i;
+        let ka = _mm256_permutevar8x32_epi32(_mm256_loadu_si256(ka_ptr), reverse);
+        let va = _mm256_permutevar8x32_epi32(_mm256_loadu_si256(va_ptr), reverse);
+        let (kb, vb) = (_mm256_loadu_si256(kb_ptr), _mm256_loadu_si256(vb_ptr));
+        let swap = swap_mask(ka, kb, forward);
+        let (new_ka, new_va) = (
+            _mm256_blendv_epi8(ka, kb, swap),
+            _mm256_blendv_epi8(va, vb, swap),
+        );
+        _mm256_storeu_si256(ka_ptr, _mm256_permutevar8x32_epi32(new_ka, reverse));
+        _mm256_storeu_si256(kb_ptr, _mm256_blendv_epi8(kb, ka, swap));
+        _mm256_storeu_si256(va_ptr, _mm256_permutevar8x32_epi32(new_va, reverse));
+        _mm256_storeu_si256(vb_ptr, _mm256_blendv_epi8(vb, va, swap));
+        i += 8;
+    }
+    scalar_pairs().flip_and_swap(
+        Columns::new(
+            &mut first.keys[..mid_point - i],
+            &mut first.values[..mid_point - i],
+        ),
+        Columns::new(&mut second.keys[i..], &mut second.values[i..]),
+        forward,
+    );
+}
+
+// 比較交換の実装をu32とi32で切り替えるためのトレイト
+// firstとsecondは分割済みの前半と後半で、secondの長さ分だけ比較する
+trait Lane: Copy + Ord + Send {
+    // first[i]とsecond[i]を比較交換する
+    fn compare_and_swap(first: &mut [Self], second: &mut [Self], forward: bool, avx2: bool);
+    // first[first.len() - 1 - i]とsecond[i]を比較交換する
+    fn flip_and_swap(first: &mut [Self], second: &mut [Self], forward: bool, avx2: bool);
+}
+
+// AVX2が使えないときのスカラー版
+fn scalar_compare_and_swap<T: Ord>(first: &mut [T], second: &mut [T], forward: bool) {
+    for (a, b) in first.iter_mut().zip(second.iter_mut()) {
+        if (*a > *b) == forward {
+            std::mem::swap(a, b);
+        }
+    }
+}
+
+fn scalar_flip_and_swap<T: Ord>(first: &mut [T], second: &mut [T], forward: bool) {
+    for (a, b) in first.iter_mut().rev().zip(second.iter_mut()) {
+        if (*a > *b) == forward {
+            std::mem::swap(a, b);
+        }
+    }
+}
+
+// 型ごとに使うmin/max命令だけが異なるので、マクロで実装を生成する
+macro_rules! impl_lane {
+    ($t:ty, $min:ident, $max:ident, $cas:ident, $flip:ident) => {
+        impl Lane for $t {
+            fn compare_and_swap(
+                first: &mut [Self],
+                second: &mut [Self],
+                forward: bool,
+                avx2: bool,
+            ) {
+                #[cfg(target_arch = "x86_64")]
+                {
+                    if avx2 {
+                        unsafe { $cas(first, second, forward) };
+                        return;
+                    }
+                }
+                #[cfg(not(target_arch = "x86_64"))]
+                let _ = avx2;
+                scalar_compare_and_swap(first, second, forward)
+            }
+
+            fn flip_and_swap(first: &mut [Self], second: &mut [Self], forward: bool, avx2: bool) {
+                #[cfg(target_arch = "x86_64")]
+                {
+                    if avx2 {
+                        unsafe { $flip(first, second, forward) };
+                        return;
+                    }
+                }
+                #[cfg(not(target_arch = "x86_64"))]
+                let _ = avx2;
+                scalar_flip_and_swap(first, second, forward)
+            }
+        }
+
+        // 8要素ずつ読み込み、minとmaxを前半と後半に書き戻す
+        // 8要素に満たない残りはスカラー版で処理する
+        #[cfg(target_arch = "x86_64")]
+        #[target_feature(enable = "avx2")]
+        unsafe fn $cas(first: &mut [$t], second: &mut [$t], forward: bool) {
+            let len = second.len();
+            let mut i = 0;
+            while i + 8 <= len {
+                let a_ptr = first.as_mut_ptr().add(i) as *mut __m256i;
+                let b_ptr = second.as_mut_ptr().add(i) as *mut __m256i;
+                let a = _mm256_loadu_si256(a_ptr);
+                let b = _mm256_loadu_si256(b_ptr);
+                let (lo, hi) = ($min(a, b), $max(a, b));
+                if forward {
+                    _mm256_storeu_si256(a_ptr, lo);
+                    _mm256_storeu_si256(b_ptr, hi);
+                } else {
+                    _mm256_storeu_si256(a_ptr, hi);
+                    _mm256_storeu_si256(b_ptr, lo);
+                }
+                i += 8;
+            }
+            scalar_compare_and_swap(&mut first[i..len], &mut second[i..], forward);
+        }
+
+        // 前半は末尾から読むので、読み込んだ8要素を逆順に並べ替えてから比較する
+        #[cfg(target_arch = "x86_64")]
+        #[target_feature(enable = "avx2")]
+        unsafe fn $flip(first: &mut [$t], second: &mut [$t], forward: bool) {
+            let len = second.len();
+            let mid_point = first.len();
+            let reverse = _mm256_setr_epi32(7, 6, 5, 4, 3, 2, 1, 0);
+            let mut i = 0;
+            while i + 8 <= len {
+                let a_ptr = first.as_mut_ptr().add(mid_point - 8 - i) as *mut __m256i;
+                let b_ptr = second.as_mut_ptr().add(i) as *mut __m256i;
+                let a = _mm256_permutevar8x32_epi32(_mm256_loadu_si256(a_ptr), reverse);
+                let b = _mm256_loadu_si256(b_ptr);
+                let (lo, hi) = ($min(a, b), $max(a, b));
+                let (a, b) = if forward { (lo, hi) } else { (hi, lo) };
+                _mm256_storeu_si256(a_ptr, _mm256_permutevar8x32_epi32(a, reverse));
+                _mm256_storeu_si256(b_ptr, b);
+                i += 8;
+            }
+            scalar_flip_and_swap(&mut first[..mid_point - i], &mut second[i..], forward);
+        }
+    };
+}
+
+impl_lane!(
+    u32,
+    _mm256_min_epu32,
+    _mm256_max_epu32,
+    compare_and_swap_u32_avx2,
+    flip_and_swap_u32_avx2
+);
+impl_lane!(
+    i32,
+    _mm256_min_epi32,
+    _mm256_max_epi32,
+    compare_and_swap_i32_avx2,
+    flip_and_swap_i32_avx2
+);
+
+#[cfg(test)]
+mod tests {
+    use super::{has_avx2, sort_i32, sort_pairs_u32, sort_u32, Lanes};
+    use crate::fourth::{self, Columns};
+    use crate::pairs::sort_pairs;
+    use crate::sorter::Sorter;
+    use crate::utils::{is_sorted_ascending, is_sorted_descending, new_u32_vec};
+    use crate::SortOrder::*;
+    #[test]
+    fn sort_u32_ascending() {
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(sort_u32(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![4, 10, 11, 20, 21, 30, 110, 330]);
+    }
+
+    #[test]
+    fn sort_u32_descending() {
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(sort_u32(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 110, 30, 21, 20, 11, 10, 4]);
+    }
+
+    #[test]
+    fn sort_i32_with_negatives() {
+        // 符号付きの比較になっていることを確かめる
+        let mut x: Vec<i32> = vec![10, -30, 11, i32::MIN, -4, 330, i32::MAX, 0, -1];
+        assert_eq!(sort_i32(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![i32::MIN, -30, -4, -1, 0, 10, 11, 330, i32::MAX]);
+        assert_eq!(sort_i32(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![i32::MAX, 330, 11, 10, 0, -1, -4, -30, i32::MIN]);
+    }
+
+    #[test]
+    fn sort_u32_large_and_odd_lengths() {
+        // 8要素に満たない端数と、並列に処理される長さを含める
+        for &len in [0, 1, 7, 9, 17, 100, 1021, 65536, 65537].iter() {
+            let mut x = new_u32_vec(len);
+            let mut expected = x.clone();
+            expected.sort();
+            assert_eq!(sort_u32(&mut x, &Ascending), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+
+            let mut x = new_u32_vec(len);
+            assert_eq!(sort_u32(&mut x, &Descending), Ok(()));
+            assert!(is_sorted_descending(&x), "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn scalar_fallback_matches_avx2() {
+        // AVX2のないCPUで使われるスカラー版でも同じ結果になる
+        let original: Vec<i32> = new_u32_vec(10_000).into_iter().map(|v| v as i32).collect();
+        let config = Sorter::new().parallel_threshold(1024);
+        let mut scalar = original.clone();
+        fourth::do_sort(
+            Columns::keys(&mut scalar),
+            true,
+            &Lanes { avx2: false },
+            &config,
+        );
+        assert!(is_sorted_ascending(&scalar));
+
+        let mut detected = original;
+        fourth::do_sort(
+            Columns::keys(&mut detected),
+            true,
+            &Lanes { avx2: has_avx2() },
+            &config,
+        );
+        assert_eq!(scalar, detected);
+    }
+
+    #[test]
+    fn sort_pairs_u32_matches_generic() {
+        // キーが重複していても、pairs::sort_pairsと同じ結果になる
+        for &len in [0, 1, 7, 9, 17, 100, 1021, 65537].iter() {
+            let keys: Vec<u32> = new_u32_vec(len).into_iter().map(|k| k % 64).collect();
+            let values: Vec<u32> = (0..len as u32).collect();
+            for &order in [Ascending, Descending].iter() {
+                let (mut expected_keys, mut expected_values) = (keys.clone(), values.clone());
+                assert_eq!(
+                    sort_pairs(&mut expected_keys, &mut expected_values, &order),
+                    Ok(())
+                );
+                let (mut k, mut v) = (keys.clone(), values.clone());
+                assert_eq!(sort_pairs_u32(&mut k, &mut v, &order), Ok(()));
+                assert_eq!((&k, &v), (&expected_keys, &expected_values), "len: {}", len);
+
+                // スカラー版でも同じになる
+                let (mut k, mut v) = (keys.clone(), values.clone());
+                let config = Sorter::new().parallel_threshold(1024);
+                let columns = Columns::new(&mut k, &mut v);
+                fourth::do_sort(columns, order == Ascending, &Lanes { avx2: false }, &config);
+                assert_eq!((k, v), (expected_keys, expected_values), "len: {}", len);
+            }
+        }
+        assert!(sort_pairs_u32(&mut [1, 2], &mut [1], &Ascending).is_err());
+    }
+}
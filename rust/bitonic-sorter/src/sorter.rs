@@ -0,0 +1,269 @@
+// 並列ソートの設定をまとめたビルダー
+// fourth::sortなどは、既定の設定のSorterでソートする
+//
+// let sorter = Sorter::new()
+//     .parallel_threshold(1 << 16)
+//     .base_case(BaseCase::Insertion { max_len: 16 });
+// sorter.sort(&mut x, &SortOrder::Ascending)?;
+
+use super::fourth::{self, Columns};
+use super::pairs;
+use super::{SortError, SortOrder};
+use rayon::ThreadPool;
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
+
+// 並列に処理するかを決める閾値の既定値
+// 分割後の長さがこれ以上なら、前半と後半をrayon::joinで並列にソートする
+pub const DEFAULT_PARALLEL_THRESHOLD: usize = 4096;
+
+// 要素のバイト数ごとの閾値の表
+// (要素のバイト数, 閾値)の組を持つ。設定されていないバイト数の要素には既定値を使う
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Thresholds(Vec<(usize, usize)>);
+
+impl Thresholds {
+    pub(crate) const fn new() -> Self {
+        Thresholds(Vec::new())
+    }
+
+    pub(crate) fn set(&mut self, element_size: usize, threshold: usize) {
+        match self.0.iter_mut().find(|(size, _)| *size == element_size) {
+            Some(entry) => entry.1 = threshold,
+            None => self.0.push((element_size, threshold)),
+        }
+    }
+
+    pub(crate) fn get(&self, element_size: usize) -> usize {
+        self.0
+            .iter()
+            .find(|(size, _)| *size == element_size)
+            .map_or(DEFAULT_PARALLEL_THRESHOLD, |&(_, threshold)| threshold)
+    }
+}
+
+// tuning::Calibration::apply()で設定された閾値
+pub(crate) static TUNED_THRESHOLDS: Mutex<Thresholds> = Mutex::new(Thresholds::new());
+
+// 並列に処理しない小さなブロックをソートする方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaseCase {
+    // 長さ1になるまでバイトニックソートの再帰を続ける
+    Bitonic,
+    // 長さmax_len以下になったブロックは挿入ソートでソートする
+    // 挿入ソートは長さの2乗に比例して遅くなるので、max_lenは小さくする
+    Insertion { max_len: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct Sorter {
+    // Noneなら、ソートする要素のバイト数に合わせてtuningで調整した値か既定値を使う
+    parallel_threshold: Option<usize>,
+    base_case: BaseCase,
+    // Noneならrayonのグローバルなスレッドプールで実行する
+    pool: Option<Arc<ThreadPool>>,
+}
+
+impl Default for Sorter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sorter {
+    // fourth::sortと同じ既定の設定
+    // 閾値は、ソートする要素と同じバイト数でtuningで調整した値があればそれを使う
+    pub fn new() -> Self {
+        Self {
+            parallel_threshold: None,
+            base_case: BaseCase::Bitonic,
+            pool: None,
+        }
+    }
+
+    // 並列に処理するかを決める閾値を設定する
+    // コア数の多いマシンでは小さく、少ないマシンでは大きくするとよい
+    pub fn parallel_threshold(mut self, threshold: usize) -> Self {
+        self.parallel_threshold = Some(threshold);
+        self
+    }
+
+    pub fn base_case(mut self, base_case: BaseCase) -> Self {
+        self.base_case = base_case;
+        self
+    }
+
+    // 指定したスレッドプールの中でソートする
+    pub fn thread_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    // 閾値が指定されていなければ、要素の型Tのバイト数に合わせた閾値を設定したSorterを返す
+    // ソートの開始時に一度だけ呼び、再帰の中ではget_parallel_thresholdで読む
+    pub(crate) fn resolve<T>(&self) -> Self {
+        let tuned = TUNED_THRESHOLDS.lock().unwrap_or_else(|e| e.into_inner());
+        self.resolve_with::<T>(&tuned)
+    }
+
+    // resolveと同じだが、tuningで調整した閾値の代わりにtunedの表を使う
+    pub(crate) fn resolve_with<T>(&self, tuned: &Thresholds) -> Self {
+        let threshold = self
+            .parallel_threshold
+            .unwrap_or_else(|| tuned.get(std::mem::size_of::<T>()));
+        Self {
+            parallel_threshold: Some(threshold),
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn get_parallel_threshold(&self) -> usize {
+        self.parallel_threshold
+            .unwrap_or(DEFAULT_PARALLEL_THRESHOLD)
+    }
+
+    pub(crate) fn get_base_case(&self) -> BaseCase {
+        self.base_case
+    }
+
+    pub fn sort<T: Ord + Send>(&self, x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+        match *order {
+            SortOrder::Ascending => self.sort_by(x, &|a, b| a.cmp(b)),
+            SortOrder::Descending => self.sort_by(x, &|a, b| b.cmp(a)),
+        }
+    }
+
+    pub fn sort_by<T, F>(&self, x: &mut [T], comparator: &F) -> Result<(), SortError>
+    where
+        T: Send,
+        F: Sync + Fn(&T, &T) -> Ordering,
+    {
+        let config = self.resolve::<T>();
+        self.install(|| fourth::do_sort(Columns::keys(x), true, comparator, &config));
+        Ok(())
+    }
+
+    // keysをソートし、キーを交換するたびにvaluesの同じ位置の要素も交換する
+    // キーが等しい要素の順序は保たれない
+    pub fn sort_pairs<K, V>(
+        &self,
+        keys: &mut [K],
+        values: &mut [V],
+        order: &SortOrder,
+    ) -> Result<(), SortError>
+    where
+        K: Ord + Send,
+        V: Send,
+    {
+        pairs::check_lengths(keys, values)?;
+        let config = self.resolve::<K>();
+        let forward = *order == SortOrder::Ascending;
+        self.install(|| {
+            let x = Columns::new(keys, values);
+            fourth::do_sort(x, forward, &|a: &K, b: &K| a.cmp(b), &config)
+        });
+        Ok(())
+    }
+
+    // installに渡したクロージャはプールのスレッドで実行され、
+    // その中のrayon::joinも同じプールで実行される
+    fn install<F: Send + FnOnce()>(&self, op: F) {
+        match self.pool {
+            Some(ref pool) => pool.install(op),
+            None => op(),
+        }
+    }
+
+    pub fn sort_by_key<T, K, F>(&self, x: &mut [T], key: &F) -> Result<(), SortError>
+    where
+        T: Send,
+        K: Ord,
+        F: Sync + Fn(&T) -> K,
+    {
+        self.sort_by(x, &|a, b| key(a).cmp(&key(b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BaseCase, Sorter};
+    use crate::utils::{is_sorted_ascending, is_sorted_descending, new_u32_vec};
+    use crate::SortOrder::*;
+    use rayon::ThreadPoolBuilder;
+    use std::sync::Arc;
+
+    #[test]
+    fn sort_with_parallel_thresholds() {
+        // 閾値を変えても同じ結果になる
+        for &threshold in [0, 1, 64, 4096, usize::MAX].iter() {
+            let sorter = Sorter::new().parallel_threshold(threshold);
+            let mut x = new_u32_vec(10_000);
+            assert_eq!(sorter.sort(&mut x, &Ascending), Ok(()));
+            assert!(is_sorted_ascending(&x), "threshold: {}", threshold);
+        }
+    }
+
+    #[test]
+    fn sort_with_insertion_base_case() {
+        for &max_len in [1, 2, 16, 100].iter() {
+            let sorter = Sorter::new()
+                .parallel_threshold(256)
+                .base_case(BaseCase::Insertion { max_len });
+            for &len in [0, 1, 15, 16, 17, 1000, 4097].iter() {
+                let mut x = new_u32_vec(len);
+                assert_eq!(sorter.sort(&mut x, &Descending), Ok(()));
+                assert!(is_sorted_descending(&x), "len: {}", len);
+            }
+        }
+    }
+
+    #[test]
+    fn sort_inside_thread_pool() {
+        // 比較関数がすべてプールのスレッドで呼ばれることを確かめる
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(2)
+            .thread_name(|i| format!("sorter-test-{}", i))
+            .build()
+            .unwrap();
+        let sorter = Sorter::new()
+            .parallel_threshold(16)
+            .thread_pool(Arc::new(pool));
+        let mut x = new_u32_vec(1000);
+        let comparator = |a: &u32, b: &u32| {
+            let name = std::thread::current().name().unwrap_or("").to_string();
+            assert!(name.starts_with("sorter-test-"), "thread: {}", name);
+            a.cmp(b)
+        };
+        assert_eq!(sorter.sort_by(&mut x, &comparator), Ok(()));
+        assert!(is_sorted_ascending(&x));
+    }
+
+    #[test]
+    fn sort_pairs_with_sorter() {
+        // キーと値の組も、同じ設定のネットワークでソートする
+        let pool = ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let sorter = Sorter::new()
+            .parallel_threshold(64)
+            .base_case(BaseCase::Insertion { max_len: 8 })
+            .thread_pool(Arc::new(pool));
+        let mut keys = new_u32_vec(1000);
+        let mut values: Vec<u64> = keys.iter().map(|&k| k as u64 * 3).collect();
+        assert_eq!(
+            sorter.sort_pairs(&mut keys, &mut values, &Descending),
+            Ok(())
+        );
+        assert!(is_sorted_descending(&keys));
+        assert!(keys.iter().zip(&values).all(|(&k, &v)| v == k as u64 * 3));
+        assert!(sorter
+            .sort_pairs(&mut keys, &mut values[1..], &Ascending)
+            .is_err());
+    }
+
+    #[test]
+    fn sort_by_key_with_sorter() {
+        let mut x = vec!["Rust", "is", "quick", "and", "memory-efficient"];
+        let sorter = Sorter::new().base_case(BaseCase::Insertion { max_len: 4 });
+        assert_eq!(sorter.sort_by_key(&mut x, &|s| s.len()), Ok(()));
+        assert_eq!(x, vec!["is", "and", "Rust", "quick", "memory-efficient"]);
+    }
+}
@@ -9,11 +9,12 @@
 // Rustでは関数、変数、定数にスネークケース、
 // ユーザが定義した型やジェネリクス型パラメータの識別子にはキャメルケースを用いる
 
-use super::SortOrder;
+use super::permutation::{identity, permute_in_place, SortIndex};
+use super::{power_of_two_below, SortError, SortOrder};
 use std::cmp::Ordering;
 
 // match式による場合分けをしてdo_sort()に渡す
-pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
+pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
     // do_sort()を呼ぶ代わりに、sort_by()を呼ぶようにする
 
     match *order {
@@ -26,38 +27,231 @@ pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
 // 第2引数comparatorはクロージャを受け取る
 // クロージャの型はジェネリクスになっていて、型パラメータFで示している
 // where節以降にはFnで始まるトレイト境界が指定されている
-pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), String>
+pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
 where
     F: Fn(&T, &T) -> Ordering,
 {
-    if x.len().is_power_of_two() {
+    // 2のべき乗でない長さにも対応したので、どんな長さでも失敗しない
+    do_sort(x, true, comparator);
+    Ok(())
+}
+
+// 要素から取り出したキーで比較する
+// キーは比較のたびに計算されるので、計算が軽いときに使う
+pub fn sort_by_key<T, K, F>(x: &mut [T], key: &F) -> Result<(), SortError>
+where
+    K: Ord,
+    F: Fn(&T) -> K,
+{
+    sort_by(x, &|a, b| key(a).cmp(&key(b)))
+}
+
+// キーを要素ごとに1回だけ計算し、(キー, 元の位置)の組をソートしてからxを並べ替える
+// 元の位置でも比較するので、キーが等しい要素は元の順のまま残る
+pub fn sort_by_cached_key<T, K, F>(x: &mut [T], key: &F) -> Result<(), SortError>
+where
+    K: Ord,
+    F: Fn(&T) -> K,
+{
+    let mut keys = Vec::new();
+    keys.try_reserve_exact(x.len())
+        .map_err(|_| SortError::AllocationFailed)?;
+    keys.extend(x.iter().enumerate().map(|(i, v)| (key(v), i)));
+    sort_by(&mut keys, &|a, b| a.cmp(b))?;
+    permute_in_place(x, keys.into_iter().map(|(_, i)| i).collect());
+    Ok(())
+}
+
+// xは変更せずに、xをソートする並べ替え(置換)を返す
+// 戻り値のp番目は、ソート後にp番目に来る要素のxでの位置
+// 戻り値の型はlet order: Vec<u32> = argsort(...)?;のように指定する
+// 要素数が少ないならu32にするとメモリを節約できる
+pub fn argsort<I, T>(x: &[T], order: &SortOrder) -> Result<Vec<I>, SortError>
+where
+    I: SortIndex,
+    T: Ord,
+{
+    match *order {
+        SortOrder::Ascending => argsort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => argsort_by(x, &|a, b| b.cmp(a)),
+    }
+}
+
+// 比較結果が等しい要素は位置の順に並ぶ
+pub fn argsort_by<I, T, F>(x: &[T], comparator: &F) -> Result<Vec<I>, SortError>
+where
+    I: SortIndex,
+    F: Fn(&T, &T) -> Ordering,
+{
+    let mut indices = identity(x.len())?;
+    sort_by(&mut indices, &|a: &I, b: &I| {
+        comparator(&x[a.to_usize()], &x[b.to_usize()]).then_with(|| a.cmp(b))
+    })?;
+    Ok(indices)
+}
+
+// 安定ソート
+// comparatorで等しい要素は、元の順を保ったまま並ぶ
+// バイトニックソートは安定ではないので、argsort_byと同じく元の位置でも比較して
+// 並べ替えを求めてから、xに適用する
+pub fn stable_sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    let indices: Vec<usize> = argsort_by(x, comparator)?;
+    permute_in_place(x, indices);
+    Ok(())
+}
+
+// ソート済みの2つの列aとbを、ソート済みの1つのVecにまとめる
+// aとbはどちらもorderの順にソートされていなければならない
+// 長さは異なっていてもよい
+pub fn merge_sorted<T>(a: &[T], b: &[T], order: &SortOrder) -> Result<Vec<T>, SortError>
+where
+    T: Ord + Clone,
+{
+    match *order {
+        SortOrder::Ascending => merge_sorted_by(a, b, &|a, b| a.cmp(b)),
+        SortOrder::Descending => merge_sorted_by(a, b, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn merge_sorted_by<T, F>(a: &[T], b: &[T], comparator: &F) -> Result<Vec<T>, SortError>
+where
+    T: Clone,
+    F: Fn(&T, &T) -> Ordering,
+{
+    let mut merged = Vec::new();
+    merged
+        .try_reserve_exact(a.len() + b.len())
+        .map_err(|_| SortError::AllocationFailed)?;
+    merged.extend_from_slice(a);
+    merged.extend_from_slice(b);
+    merge_sorted_halves_by(&mut merged, a.len(), comparator)?;
+    Ok(merged)
+}
+
+// x[..mid]とx[mid..]がどちらもorderの順にソート済みのとき、x全体をその場でソートする
+// midがx.len()より大きいときはパニックする
+pub fn merge_sorted_halves<T: Ord>(
+    x: &mut [T],
+    mid: usize,
+    order: &SortOrder,
+) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => merge_sorted_halves_by(x, mid, &|a, b| a.cmp(b)),
+        SortOrder::Descending => merge_sorted_halves_by(x, mid, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn merge_sorted_halves_by<T, F>(
+    x: &mut [T],
+    mid: usize,
+    comparator: &F,
+) -> Result<(), SortError>
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    let (first, second) = x.split_at_mut(mid);
+    merge(first, second, true, comparator);
+    Ok(())
+}
+
+// 先に並ぶk個の要素をorderの順にソートしてx[..k]に集める
+// x[k..]の並びは問わない。kがx.len()以上ならx全体をソートする
+pub fn partial_sort<T: Ord>(x: &mut [T], k: usize, order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => partial_sort_by(x, k, &|a, b| a.cmp(b)),
+        SortOrder::Descending => partial_sort_by(x, k, &|a, b| b.cmp(a)),
+    }
+}
+
+// x[..k]をソートしてから、残りをk個ずつのブロックに分けて順にソートし、
+// バイトニックマージの前半だけを行って、先に並ぶk個をx[..k]に残していく
+pub fn partial_sort_by<T, F>(x: &mut [T], k: usize, comparator: &F) -> Result<(), SortError>
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    if k >= x.len() {
         do_sort(x, true, comparator);
-        Ok(())
-    } else {
-        Err(format!(
-            "The length of x is not a power of two. (x.len(): {})",
-            x.len()
-        ))
+    } else if k > 0 {
+        let (front, rest) = x.split_at_mut(k);
+        do_sort(front, true, comparator);
+        for block in rest.chunks_mut(k) {
+            do_sort(block, true, comparator);
+            keep_front(front, block, true, comparator);
+        }
     }
+    Ok(())
+}
+
+// iterの要素のうち、orderの順で先に並ぶk個をソートして返す
+// Descendingなら大きい方からk個になる
+// iterはk個ずつ読み込むので、全体を一度にメモリに載せなくてよい
+pub fn top_k<I>(iter: I, k: usize, order: &SortOrder) -> Result<Vec<I::Item>, SortError>
+where
+    I: IntoIterator,
+    I::Item: Ord,
+{
+    match *order {
+        SortOrder::Ascending => top_k_by(iter, k, &|a, b| a.cmp(b)),
+        SortOrder::Descending => top_k_by(iter, k, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn top_k_by<I, F>(iter: I, k: usize, comparator: &F) -> Result<Vec<I::Item>, SortError>
+where
+    I: IntoIterator,
+    F: Fn(&I::Item, &I::Item) -> Ordering,
+{
+    let mut iter = iter.into_iter();
+    let mut buffer: Vec<I::Item> = Vec::new();
+    if k == 0 {
+        return Ok(buffer);
+    }
+    // 最初のk個をソートしたものが、暫定の上位k個になる
+    buffer.extend(iter.by_ref().take(k));
+    do_sort(&mut buffer, true, comparator);
+    loop {
+        // 暫定の上位k個の後ろに次のk個を読み込む
+        buffer.extend(iter.by_ref().take(k));
+        if buffer.len() <= k {
+            break;
+        }
+        let (front, batch) = buffer.split_at_mut(k);
+        do_sort(batch, true, comparator);
+        keep_front(front, batch, true, comparator);
+        buffer.truncate(k);
+    }
+    Ok(buffer)
 }
 
 // u32型のみに対応している
 // pub fn sort(x: &mut [u32], up: bool) {
 // 型パラメータTを導入して、関数をジェネリクス化する
 // 全順序だけを受け取るように、型パラメータTのトレイト境界としてOrdを設定
+
+// xの長さを2のべき乗に切り上げた仮想的な列を考え、足りない部分には
+// 「どの要素よりも後ろに並ぶ値」が詰まっているものとみなす
+// その値は交換されることがないので、実際には比較を省略するだけでよい
+// そのため番兵値もT: Defaultも必要ない
 fn do_sort<T, F>(x: &mut [T], forward: bool, comparator: &F)
 where
     F: Fn(&T, &T) -> Ordering,
 {
     // スライスの参照を受け取っているため値を返す必要がない
     if x.len() > 1 {
-        let mid_point = x.len() / 2;
+        // x.len()未満で最大の2のべき乗で分割する
+        // 2のべき乗の長さなら、ちょうど半分の位置になる
+        let mid_point = power_of_two_below(x.len());
 
-        // 第2引数がtrueのときはcomparatorで示される順にソート
-        do_sort(&mut x[..mid_point], true, comparator);
-        // 第2引数がfalseのときはcomparatorとは逆順にソート
-        do_sort(&mut x[mid_point..], false, comparator);
-        sub_sort(x, forward, comparator)
+        // 前半と後半をどちらもcomparatorで示される順にソートする
+        do_sort(&mut x[..mid_point], forward, comparator);
+        do_sort(&mut x[mid_point..], forward, comparator);
+        // 後半を逆順に読むと、全体がバイトニック列になる
+        flip_and_swap(x, forward, comparator);
+        sub_sort(&mut x[..mid_point], forward, comparator);
+        sub_sort(&mut x[mid_point..], forward, comparator);
     }
 }
 
@@ -68,25 +262,97 @@ where
     // 受け取ったforward引数をcompare_and_swap関数や自分自身の再帰呼び出しにそのまま渡す
     if x.len() > 1 {
         compare_and_swap(x, forward, comparator);
-        let mid_point = x.len() / 2;
+        let mid_point = power_of_two_below(x.len());
         sub_sort(&mut x[..mid_point], forward, comparator);
         sub_sort(&mut x[mid_point..], forward, comparator);
     }
 }
 
-// 2つの要素の比較にcomparatorクロージャを使う
-fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F)
+// ソート済みのfirstとsecondをマージする
+// 2つを同じ長さ(2のべき乗)の列とみなし、firstの前には「どの要素よりも前に並ぶ値」、
+// secondの後ろには「どの要素よりも後ろに並ぶ値」が詰まっているものとして
+// do_sortの後半と同じマージを行う。詰めた値は交換されないので比較を省略する
+fn merge<T, F>(first: &mut [T], second: &mut [T], forward: bool, comparator: &F)
 where
     F: Fn(&T, &T) -> Ordering,
 {
-    // 比較に先立ちforward(bool値)をOrdering値に変換しておく
-    let swap_condition = if forward {
+    flip_between(first, second, forward, comparator);
+    sub_sort_back(first, forward, comparator);
+    sub_sort(second, forward, comparator);
+}
+
+// mergeの前半だけを行い、firstとsecondを合わせた中で先に並ぶfirst.len()個を
+// firstにソートして集める。secondの並びは問わない
+fn keep_front<T, F>(first: &mut [T], second: &mut [T], forward: bool, comparator: &F)
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    flip_between(first, second, forward, comparator);
+    sub_sort_back(first, forward, comparator);
+}
+
+// firstの末尾から前に向かって、secondの先頭から後ろに向かって要素を比較する
+// この後、firstには先に並ぶ要素が、secondには後に並ぶ要素が集まる
+fn flip_between<T, F>(first: &mut [T], second: &mut [T], forward: bool, comparator: &F)
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    let swap_condition = swap_condition(forward);
+    for (a, b) in first.iter_mut().rev().zip(second.iter_mut()) {
+        if comparator(a, b) == swap_condition {
+            std::mem::swap(a, b);
+        }
+    }
+}
+
+// 前に値が詰まっているとみなす版のsub_sort
+// 比較する組はsub_sortと同じで、後ろから2のべき乗の長さで分割して再帰する
+fn sub_sort_back<T, F>(x: &mut [T], forward: bool, comparator: &F)
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    if x.len() > 1 {
+        compare_and_swap(x, forward, comparator);
+        let mid_point = x.len() - power_of_two_below(x.len());
+        let (first, second) = x.split_at_mut(mid_point);
+        sub_sort_back(first, forward, comparator);
+        sub_sort_back(second, forward, comparator);
+    }
+}
+
+// 比較に先立ちforward(bool値)をOrdering値に変換しておく
+fn swap_condition(forward: bool) -> Ordering {
+    if forward {
         Ordering::Greater
     } else {
         Ordering::Less
-    };
-    let mid_point = x.len() / 2;
-    for i in 0..mid_point {
+    }
+}
+
+// 前半の末尾から前に向かって、後半の先頭から後ろに向かって要素を比較する
+// 例: 前半4要素、後半3要素なら(3, 4), (2, 5), (1, 6)の組を比較する
+fn flip_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F)
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    let swap_condition = swap_condition(forward);
+    let mid_point = power_of_two_below(x.len());
+    for i in 0..(x.len() - mid_point) {
+        if comparator(&x[mid_point - 1 - i], &x[mid_point + i]) == swap_condition {
+            x.swap(mid_point - 1 - i, mid_point + i)
+        }
+    }
+}
+
+// 2つの要素の比較にcomparatorクロージャを使う
+fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F)
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    let swap_condition = swap_condition(forward);
+    // 後半が前半より短いときは、後半の長さの分だけ比較する
+    let mid_point = power_of_two_below(x.len());
+    for i in 0..(x.len() - mid_point) {
         // comparatorクロージャで2要素を比較し、返されたOrderingのバリアントが
         // swap_conditionと等しいなら要素を交換する
         // comparatorクロージャはGreater, Equal, Lessのいずれかのバリアントを返す
@@ -102,9 +368,16 @@ where
 #[cfg(test)]
 mod tests {
     // 親モジュール(first)のsort関数を使用する
-    use super::{sort, sort_by};
+    use super::{
+        argsort, argsort_by, merge_sorted, merge_sorted_halves, partial_sort, sort, sort_by,
+        sort_by_cached_key, sort_by_key, stable_sort_by, top_k,
+    };
+    use crate::network::Network;
+    use crate::permutation::apply_permutation;
     use crate::utils::{is_sorted_ascending, is_sorted_descending, new_u32_vec};
+    use crate::zero_one::verify;
     use crate::SortOrder::*;
+    use std::cell::Cell;
 
     // deriveアトリビュートを使い、DebugトレイトとPartialEqトレイトの実装を自動導出する
     #[derive(Debug, PartialEq)]
@@ -192,6 +465,114 @@ mod tests {
         assert_eq!(x, expected);
     }
 
+    #[test]
+    fn sort_student_by_lowercase_last_name() {
+        // 大文字と小文字が混ざった苗字を小文字にしてから比較する
+        let taro = Student::new("Taro", "Yamada", 16);
+        let hanako = Student::new("Hanako", "sato", 14);
+        let kyoko = Student::new("Kyoko", "ito", 15);
+        let ryosuke = Student::new("Ryosuke", "Hayashi", 17);
+
+        let mut x = vec![&taro, &hanako, &kyoko, &ryosuke];
+        let expected = vec![&ryosuke, &kyoko, &hanako, &taro];
+        assert_eq!(sort_by_key(&mut x, &|s| s.last_name.to_lowercase()), Ok(()));
+        assert_eq!(x, expected);
+
+        let mut x = vec![&taro, &hanako, &kyoko, &ryosuke];
+        assert_eq!(
+            sort_by_cached_key(&mut x, &|s| s.last_name.to_lowercase()),
+            Ok(())
+        );
+        assert_eq!(x, expected);
+    }
+
+    #[test]
+    fn sort_by_cached_key_computes_each_key_once() {
+        // 2のべき乗でない長さでも、キーの計算は要素数と同じ回数だけ
+        let calls = Cell::new(0);
+        let mut x = new_u32_vec(1000);
+        let mut expected = x.clone();
+        expected.sort_by_key(|v| v % 1000);
+        assert_eq!(
+            sort_by_cached_key(&mut x, &|v| {
+                calls.set(calls.get() + 1);
+                v % 1000
+            }),
+            Ok(())
+        );
+        assert_eq!(calls.get(), 1000);
+        // キーが等しい要素は元の順のまま残るので、安定ソートの結果と一致する
+        assert_eq!(x, expected);
+    }
+
+    #[test]
+    fn argsort_keeps_input() {
+        let x = vec![10, 30, 11, 20, 4, 330, 21];
+        let order: Vec<usize> = argsort(&x, &Ascending).unwrap();
+        assert_eq!(order, vec![4, 0, 2, 3, 6, 1, 5]);
+        // 入力は変更されない
+        assert_eq!(x, vec![10, 30, 11, 20, 4, 330, 21]);
+
+        // 添字をu32で受け取ることもできる
+        let order: Vec<u32> = argsort(&x, &Descending).unwrap();
+        assert_eq!(order, vec![5, 1, 6, 3, 2, 0, 4]);
+    }
+
+    #[test]
+    fn argsort_student_columns() {
+        // 列ごとに分かれたデータを、年齢の列の並べ替えで一緒に並べ替える
+        let mut ages = vec![16, 14, 15, 17, 14];
+        let mut names = vec!["Taro", "Hanako", "Kyoko", "Ryosuke", "Ichiro"];
+        let order: Vec<u32> = argsort_by(&ages, &|a, b| a.cmp(b)).unwrap();
+        // 年齢が等しいHanakoとIchiroは元の順に並ぶ
+        assert_eq!(order, vec![1, 4, 2, 0, 3]);
+        apply_permutation(&order, &mut [&mut ages, &mut names]);
+        assert_eq!(ages, vec![14, 14, 15, 16, 17]);
+        assert_eq!(names, vec!["Hanako", "Ichiro", "Kyoko", "Taro", "Ryosuke"]);
+    }
+
+    #[test]
+    fn argsort_large() {
+        let x = new_u32_vec(65537);
+        let order: Vec<usize> = argsort(&x, &Ascending).unwrap();
+        let sorted: Vec<u32> = order.iter().map(|&i| x[i]).collect();
+        assert!(is_sorted_ascending(&sorted));
+    }
+
+    #[test]
+    fn stable_sort_student_by_age() {
+        // 同じ年齢の学生は元の順のまま並ぶ
+        let taro = Student::new("Taro", "Yamada", 16);
+        let hanako = Student::new("Hanako", "Yamada", 14);
+        let kyoko = Student::new("Kyoko", "Ito", 15);
+        let ryosuke = Student::new("Ryosuke", "Hayashi", 16);
+        let ichiro = Student::new("Ichiro", "Sato", 14);
+
+        let mut x = vec![&taro, &hanako, &kyoko, &ryosuke, &ichiro];
+        let expected = vec![&hanako, &ichiro, &kyoko, &taro, &ryosuke];
+        assert_eq!(stable_sort_by(&mut x, &|a, b| a.age.cmp(&b.age)), Ok(()));
+        assert_eq!(x, expected);
+    }
+
+    #[test]
+    fn stable_sort_keeps_tag_order() {
+        // 元の位置をタグとして持たせた組を、重複の多いキーだけで比較してソートする
+        // 同じキーの組のタグが昇順のままなら、元の順が保たれている
+        for &len in [1, 7, 100, 4097, 20000].iter() {
+            let mut x: Vec<(u32, usize)> = new_u32_vec(len)
+                .into_iter()
+                .map(|v| v % 8)
+                .enumerate()
+                .map(|(tag, key)| (key, tag))
+                .collect();
+            assert_eq!(stable_sort_by(&mut x, &|a, b| a.0.cmp(&b.0)), Ok(()));
+            for pair in x.windows(2) {
+                let ((key0, tag0), (key1, tag1)) = (pair[0], pair[1]);
+                assert!(key0 < key1 || (key0 == key1 && tag0 < tag1), "len: {}", len);
+            }
+        }
+    }
+
     // テストケースになる関数は#[test]アトリビュートを付ける
     #[test]
     fn sort_u32_ascending() {
@@ -274,8 +655,124 @@ mod tests {
     }
 
     #[test]
-    fn sort_to_fail() {
+    fn sort_odd_length() {
         let mut x = vec![10, 30, 11]; // 2のべき乗でない
-        assert!(sort(&mut x, &Ascending).is_err()); // 戻り値はErr
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![10, 11, 30]);
+
+        let mut x = vec![10, 30, 11, 20, 4, 330, 21];
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 30, 21, 20, 11, 10, 4]);
+    }
+
+    #[test]
+    fn sort_prime_and_off_by_one_lengths() {
+        // 素数の長さと、2のべき乗の前後の長さを試す
+        let lengths = [0, 1, 2, 5, 13, 31, 33, 127, 129, 1021, 4095, 4097];
+        for &len in lengths.iter() {
+            let mut x = new_u32_vec(len);
+            let mut expected = x.clone();
+            expected.sort();
+            assert_eq!(sort(&mut x, &Ascending), Ok(()));
+            assert_eq!(x, expected, "len: {}", len);
+
+            let mut x = new_u32_vec(len);
+            assert_eq!(sort(&mut x, &Descending), Ok(()));
+            assert!(is_sorted_descending(&x), "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn zero_one_principle_up_to_24() {
+        // このモジュールのsort_byが実行するネットワークを、0と1のすべての入力で検証する
+        for len in 0..=24 {
+            let network = Network::record(len, |x, c| sort_by(x, c).unwrap());
+            assert_eq!(verify(&network), Ok(()), "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn merge_sorted_halves_of_zeros_and_ones() {
+        // マージの比較器ネットワークにも0-1原理が使える
+        // 長さ12までのソート済みの0と1の列の組をすべて試す
+        for first_len in 0..=12 {
+            for second_len in 0..=12 {
+                for first_ones in 0..=first_len {
+                    for second_ones in 0..=second_len {
+                        let mut x = vec![0; first_len - first_ones];
+                        x.resize(first_len, 1);
+                        x.resize(first_len + second_len - second_ones, 0);
+                        x.resize(first_len + second_len, 1);
+                        assert_eq!(merge_sorted_halves(&mut x, first_len, &Ascending), Ok(()));
+                        assert!(is_sorted_ascending(&x), "{:?}", x);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn merge_sorted_unequal_lengths() {
+        for &(a_len, b_len) in [(0, 0), (0, 5), (7, 0), (1, 100), (1000, 3), (513, 511)].iter() {
+            let mut a = new_u32_vec(a_len);
+            let mut b = new_u32_vec(b_len);
+            for &order in [Ascending, Descending].iter() {
+                sort(&mut a, &order).unwrap();
+                sort(&mut b, &order).unwrap();
+                let mut expected = [a.clone(), b.clone()].concat();
+                sort(&mut expected, &order).unwrap();
+                assert_eq!(merge_sorted(&a, &b, &order), Ok(expected.clone()));
+
+                let mut x = [a.clone(), b.clone()].concat();
+                assert_eq!(merge_sorted_halves(&mut x, a_len, &order), Ok(()));
+                assert_eq!(x, expected, "{} + {}", a_len, b_len);
+            }
+        }
+    }
+
+    #[test]
+    fn partial_sort_matches_full_sort() {
+        for &len in [0, 1, 2, 10, 33, 1000].iter() {
+            for &k in [0, 1, 3, 7, 32, 999, 1000, 2000].iter() {
+                for &order in [Ascending, Descending].iter() {
+                    let original = new_u32_vec(len);
+                    let mut expected = original.clone();
+                    sort(&mut expected, &order).unwrap();
+
+                    let mut x = original;
+                    assert_eq!(partial_sort(&mut x, k, &order), Ok(()));
+                    let k = k.min(len);
+                    assert_eq!(x[..k], expected[..k], "len: {}, k: {}", len, k);
+                    // 残りの要素も失われていない
+                    sort(&mut x, &order).unwrap();
+                    assert_eq!(x, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn top_k_matches_full_sort() {
+        for &len in [0, 5, 100, 1001].iter() {
+            let x = new_u32_vec(len);
+            for &k in [0, 1, 10, 100, 2000].iter() {
+                let mut expected = x.clone();
+                sort(&mut expected, &Descending).unwrap();
+                expected.truncate(k);
+                assert_eq!(
+                    top_k(x.iter().cloned(), k, &Descending),
+                    Ok(expected),
+                    "len: {}, k: {}",
+                    len,
+                    k
+                );
+            }
+        }
+        // 文字列の短い方から3つ
+        let words = "the quick brown fox jumps over a lazy dog".split(' ');
+        assert_eq!(
+            top_k(words.map(|w| (w.len(), w)), 3, &Ascending),
+            Ok(vec![(1, "a"), (3, "dog"), (3, "fox")])
+        );
     }
 }
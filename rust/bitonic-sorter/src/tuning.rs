@@ -0,0 +1,225 @@
+// 並列に処理する閾値の自動調整
+// いくつかの閾値で実際にソートして時間を測り、このマシンで最も速い閾値を選ぶ
+// 結果は小さな設定ファイルに保存でき、読み込んでapply()すると
+// 以降のfourth::sortやSorter::new()が、測定したときと同じバイト数の要素のソートにその閾値を使う
+//
+// let calibration = tuning::calibrate(1 << 20, &tuning::DEFAULT_CANDIDATES, 3);
+// calibration.save("bitonic-sorter.conf")?;
+// ...
+// tuning::Calibration::load("bitonic-sorter.conf")?.apply();
+
+use super::sorter::{Sorter, Thresholds, TUNED_THRESHOLDS};
+use super::utils::new_u32_vec;
+use super::SortOrder;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// calibrateで試す閾値の候補
+pub const DEFAULT_CANDIDATES: [usize; 6] = [256, 1024, 4096, 16384, 65536, 262_144];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Calibration {
+    // 最も速かった閾値
+    pub parallel_threshold: usize,
+    // 測定したときのrayonのスレッド数
+    pub threads: usize,
+    // 測定に使った要素のバイト数
+    pub element_size: usize,
+}
+
+// 要素数lenのu32のベクタを、候補の閾値ごとにrepeats回ソートして
+// 最も短かった時間で比べる
+pub fn calibrate(len: usize, candidates: &[usize], repeats: usize) -> Calibration {
+    assert!(!candidates.is_empty(), "no candidates to calibrate");
+    let original = new_u32_vec(len);
+    // (最短時間, 閾値)の組の最小を求める
+    let (_, parallel_threshold) = candidates
+        .iter()
+        .map(|&threshold| (measure(&original, threshold, repeats), threshold))
+        .min()
+        .unwrap();
+    Calibration {
+        parallel_threshold,
+        threads: rayon::current_num_threads(),
+        element_size: std::mem::size_of::<u32>(),
+    }
+}
+
+fn measure(original: &[u32], threshold: usize, repeats: usize) -> Duration {
+    let sorter = Sorter::new().parallel_threshold(threshold);
+    (0..repeats.max(1))
+        .map(|_| {
+            let mut x = original.to_vec();
+            let start = Instant::now();
+            sorter.sort(&mut x, &SortOrder::Ascending).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+impl Calibration {
+    // このマシンで測定した結果なら、element_sizeバイトの要素をソートするときの
+    // 既定の閾値として設定してtrueを返す。他のバイト数の要素の閾値は変わらない
+    // スレッド数が異なるマシンで測定した結果なら何もせずfalseを返す
+    pub fn apply(&self) -> bool {
+        let mut tuned = TUNED_THRESHOLDS.lock().unwrap_or_else(|e| e.into_inner());
+        self.apply_to(&mut tuned)
+    }
+
+    // applyと同じだが、グローバルな設定の代わりにthresholdsの表を書き換える
+    pub(crate) fn apply_to(&self, thresholds: &mut Thresholds) -> bool {
+        if self.threads == rayon::current_num_threads() {
+            thresholds.set(self.element_size, self.parallel_threshold);
+            true
+        } else {
+            false
+        }
+    }
+
+    // key=valueの形式で1行に1項目ずつ書き出す
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(
+            path,
+            format!(
+                "parallel_threshold={}\nthreads={}\nelement_size={}\n",
+                self.parallel_threshold, self.threads, self.element_size
+            ),
+        )
+    }
+
+    // 空行と#で始まる行は読み飛ばす
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut parallel_threshold = None;
+        let mut threads = None;
+        let mut element_size = None;
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+                None => return Err(invalid_data(format!("missing '=': {}", line))),
+            };
+            let value: usize = value
+                .parse()
+                .map_err(|_| invalid_data(format!("invalid number: {}", line)))?;
+            match key {
+                "parallel_threshold" => parallel_threshold = Some(value),
+                "threads" => threads = Some(value),
+                "element_size" => element_size = Some(value),
+                _ => return Err(invalid_data(format!("unknown key: {}", key))),
+            }
+        }
+        match (parallel_threshold, threads, element_size) {
+            (Some(parallel_threshold), Some(threads), Some(element_size)) => Ok(Self {
+                parallel_threshold,
+                threads,
+                element_size,
+            }),
+            _ => Err(invalid_data("missing keys".to_string())),
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{calibrate, Calibration};
+    use crate::sorter::{Sorter, Thresholds, DEFAULT_PARALLEL_THRESHOLD};
+    use std::fs;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn calibrate_picks_a_candidate() {
+        let candidates = [64, 1024];
+        let calibration = calibrate(1 << 12, &candidates, 1);
+        assert!(candidates.contains(&calibration.parallel_threshold));
+        assert_eq!(calibration.threads, rayon::current_num_threads());
+        assert_eq!(calibration.element_size, 4);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("bitonic-sorter-{}.conf", std::process::id()));
+        let calibration = Calibration {
+            parallel_threshold: 16384,
+            threads: 8,
+            element_size: 4,
+        };
+        calibration.save(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "parallel_threshold=16384\nthreads=8\nelement_size=4\n"
+        );
+        assert_eq!(Calibration::load(&path).unwrap(), calibration);
+
+        // 壊れたファイルはInvalidDataになる
+        fs::write(&path, "parallel_threshold=many\n").unwrap();
+        let err = Calibration::load(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn apply_changes_default_sorter() {
+        // グローバルな設定は他のテストと共有しているので、テストでは自前の表に適用する
+        let mut tuned = Thresholds::new();
+        let calibration = Calibration {
+            parallel_threshold: 12345,
+            threads: rayon::current_num_threads(),
+            element_size: 4,
+        };
+        assert!(calibration.apply_to(&mut tuned));
+        assert_eq!(threshold_for::<u32>(&tuned), 12345);
+
+        // スレッド数が異なる結果は使わない
+        let other = Calibration {
+            parallel_threshold: 64,
+            threads: rayon::current_num_threads() + 1,
+            ..calibration
+        };
+        assert!(!other.apply_to(&mut tuned));
+        assert_eq!(threshold_for::<u32>(&tuned), 12345);
+    }
+
+    #[test]
+    fn apply_only_to_same_element_size() {
+        // 2バイトの要素で測定した結果は、2バイトの要素のソートにだけ使う
+        let mut tuned = Thresholds::new();
+        let calibration = Calibration {
+            parallel_threshold: 777,
+            threads: rayon::current_num_threads(),
+            element_size: 2,
+        };
+        assert!(calibration.apply_to(&mut tuned));
+        assert_eq!(threshold_for::<u16>(&tuned), 777);
+        assert_eq!(threshold_for::<i16>(&tuned), 777);
+        assert_eq!(threshold_for::<u64>(&tuned), DEFAULT_PARALLEL_THRESHOLD);
+        assert_eq!(
+            threshold_for::<(u64, u64)>(&tuned),
+            DEFAULT_PARALLEL_THRESHOLD
+        );
+
+        // 明示的に指定した閾値は、調整した値より優先される
+        assert_eq!(
+            Sorter::new()
+                .parallel_threshold(64)
+                .resolve_with::<u16>(&tuned)
+                .get_parallel_threshold(),
+            64
+        );
+    }
+
+    fn threshold_for<T>(tuned: &Thresholds) -> usize {
+        Sorter::new()
+            .resolve_with::<T>(tuned)
+            .get_parallel_threshold()
+    }
+}
@@ -1,6 +1,10 @@
 use rand::distributions::Standard;
 use rand::{Rng, SeedableRng};
 use rand_pcg::Pcg64Mcg;
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 
 pub fn new_u32_vec(n: usize) -> Vec<u32> {
     // RNGを初期化する。再現性を持たせるために毎回同じシード値を使う
@@ -35,3 +39,412 @@ pub fn is_sorted_ascending<T: Ord>(x: &[T]) -> bool {
 pub fn is_sorted_descending<T: Ord>(x: &[T]) -> bool {
     x.windows(2).all(|pair| pair[0] >= pair[1])
 }
+
+// 比較関数comparatorの順にソートできていることを確認する関数
+// 隣り合う要素がどれもcomparatorでGreaterにならなければソートできている
+pub fn is_sorted_by<T, F>(x: &[T], comparator: &F) -> bool
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    check_sorted(x, comparator).is_ok()
+}
+
+// ソートできていなければ、順序が崩れている最初の位置iをErr(i)で返す
+// x[i - 1]とx[i]の順序が逆になっている
+pub fn check_sorted<T, F>(x: &[T], comparator: &F) -> Result<(), usize>
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    match x
+        .windows(2)
+        .position(|pair| comparator(&pair[0], &pair[1]) == Ordering::Greater)
+    {
+        Some(i) => Err(i + 1),
+        None => Ok(()),
+    }
+}
+
+// sortedがoriginalを並べ替えたものになっていることを確認する関数
+// ソートで要素が失われたり重複したりしていないかを調べるのに使う
+// 要素のハッシュ値の和のように順序によらない値(多重集合のハッシュ値)を比べるので、
+// falseなら確実に並べ替えではないが、trueでもごくまれに誤判定することがある
+pub fn is_permutation_of<T: Hash>(original: &[T], sorted: &[T]) -> bool {
+    original.len() == sorted.len() && multiset_hash(original) == multiset_hash(sorted)
+}
+
+// 並列版の確認関数
+// 2^28要素のような大きなベンチマークで、確認に時間がかからないようにする
+
+pub fn par_is_sorted_ascending<T: Ord + Sync>(x: &[T]) -> bool {
+    par_is_sorted_by(x, &|a: &T, b: &T| a.cmp(b))
+}
+
+pub fn par_is_sorted_descending<T: Ord + Sync>(x: &[T]) -> bool {
+    par_is_sorted_by(x, &|a: &T, b: &T| b.cmp(a))
+}
+
+pub fn par_is_sorted_by<T, F>(x: &[T], comparator: &F) -> bool
+where
+    T: Sync,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    x.par_windows(2)
+        .all(|pair| comparator(&pair[0], &pair[1]) != Ordering::Greater)
+}
+
+// 順序が崩れている位置が複数あっても、check_sortedと同じく最初の位置を返す
+pub fn par_check_sorted<T, F>(x: &[T], comparator: &F) -> Result<(), usize>
+where
+    T: Sync,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    match x
+        .par_windows(2)
+        .position_first(|pair| comparator(&pair[0], &pair[1]) == Ordering::Greater)
+    {
+        Some(i) => Err(i + 1),
+        None => Ok(()),
+    }
+}
+
+pub fn par_is_permutation_of<T: Hash + Sync>(original: &[T], sorted: &[T]) -> bool {
+    original.len() == sorted.len() && par_multiset_hash(original) == par_multiset_hash(sorted)
+}
+
+// 多重集合のハッシュ値
+// 要素ごとのハッシュ値を2通りに混ぜて、それぞれの和をとる
+// 和は要素の順序によらないので、並べ替えても同じ値になる
+fn multiset_hash<T: Hash>(x: &[T]) -> (u64, u64) {
+    x.iter().map(element_hash).fold((0, 0), add_hashes)
+}
+
+fn par_multiset_hash<T: Hash + Sync>(x: &[T]) -> (u64, u64) {
+    x.par_iter().map(element_hash).reduce(|| (0, 0), add_hashes)
+}
+
+fn element_hash<T: Hash>(value: &T) -> (u64, u64) {
+    // DefaultHasher::new()はいつも同じ鍵を使うので、同じ値は同じハッシュ値になる
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    let h = hasher.finish();
+    // 2つめの値はsplitmix64の混ぜ方で作る
+    let mut m = h ^ (h >> 30);
+    m = m.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    m ^= m >> 27;
+    m = m.wrapping_mul(0x94d0_49bb_1331_11eb);
+    (h, m ^ (m >> 31))
+}
+
+fn add_hashes(a: (u64, u64), b: (u64, u64)) -> (u64, u64) {
+    (a.0.wrapping_add(b.0), a.1.wrapping_add(b.1))
+}
+
+// ベンチマークに使う入力の分布
+// 一様乱数だけでは、すでにソート済みの入力や重複の多い入力のように、
+// 実際に遅くなりやすい入力を試せないので、よく使われる分布をそろえておく
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    // 型の範囲全体からの一様乱数
+    Uniform,
+    // 昇順にソート済み
+    Sorted,
+    // 降順にソート済み
+    Reversed,
+    // 昇順にソート済みの列から、ランダムに選んだ2要素の交換をswaps回行ったもの
+    NearlySorted { swaps: usize },
+    // values種類の値だけからなる乱数
+    FewUnique { values: usize },
+    // values種類の値のうち、r番目に小さい値が1/r^exponentに比例する頻度で現れる乱数
+    // exponentは有限の値であること
+    Zipf { values: usize, exponent: f64 },
+    // 前半は昇順、後半は降順 (例: 0, 1, 2, 3, 3, 2, 1, 0)
+    OrganPipe,
+    // 長さperiodの昇順の列のくり返し (例: 0, 1, 2, 0, 1, 2, 0, 1)
+    Sawtooth { period: usize },
+}
+
+// new_vecで生成できる整数型
+pub trait Integer: Copy {
+    const BITS: u32;
+    // 0から2^BITS-1までの値を、型の最小値から最大値までへ順序を保って対応させる
+    fn from_offset(offset: u64) -> Self;
+}
+
+macro_rules! impl_integer {
+    ($($t:ty),*) => {
+        $(
+            impl Integer for $t {
+                const BITS: u32 = (std::mem::size_of::<$t>() * 8) as u32;
+
+                fn from_offset(offset: u64) -> Self {
+                    // 符号付き整数では、最上位ビットを反転させると順序が保たれる
+                    (offset as $t).wrapping_add(<$t>::MIN)
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+// distributionに従うn要素のベクタを生成する
+// 同じseedからは、いつも同じベクタが生成される
+pub fn new_vec<T: Integer>(n: usize, distribution: Distribution, seed: u64) -> Vec<T> {
+    let mut rng = Pcg64Mcg::seed_from_u64(seed);
+    match distribution {
+        Distribution::Uniform => (0..n)
+            .map(|_| T::from_offset(rng.gen::<u64>() >> (64 - T::BITS)))
+            .collect(),
+        Distribution::Sorted => (0..n).map(|i| spread(i, n)).collect(),
+        Distribution::Reversed => (0..n).rev().map(|i| spread(i, n)).collect(),
+        Distribution::NearlySorted { swaps } => {
+            let mut v: Vec<T> = (0..n).map(|i| spread(i, n)).collect();
+            if n > 1 {
+                for _ in 0..swaps {
+                    let a = rng.gen_range(0, n);
+                    let b = rng.gen_range(0, n);
+                    v.swap(a, b);
+                }
+            }
+            v
+        }
+        Distribution::FewUnique { values } => {
+            let values = values.max(1);
+            (0..n)
+                .map(|_| spread(rng.gen_range(0, values), values))
+                .collect()
+        }
+        Distribution::Zipf { values, exponent } => {
+            // 累積の重みを作っておき、一様乱数を二分探索して順位を決める
+            let values = values.max(1);
+            let mut total = 0.0;
+            let cumulative: Vec<f64> = (1..=values)
+                .map(|rank| {
+                    total += 1.0 / (rank as f64).powf(exponent);
+                    total
+                })
+                .collect();
+            (0..n)
+                .map(|_| {
+                    let target = rng.gen::<f64>() * total;
+                    let rank = match cumulative.binary_search_by(|w| w.total_cmp(&target)) {
+                        Ok(i) | Err(i) => i.min(values - 1),
+                    };
+                    spread(rank, values)
+                })
+                .collect()
+        }
+        Distribution::OrganPipe => {
+            let half = n.div_ceil(2);
+            (0..n).map(|i| spread(i.min(n - 1 - i), half)).collect()
+        }
+        Distribution::Sawtooth { period } => {
+            let period = period.max(1);
+            (0..n).map(|i| spread(i % period, period)).collect()
+        }
+    }
+}
+
+// 0からn-1までのiを、型の範囲へ順序を保って均等に散らばらせる
+// 型の範囲がnより狭いときは、隣り合うiが同じ値になる
+fn spread<T: Integer>(i: usize, n: usize) -> T {
+    T::from_offset((((i as u128) << T::BITS) / n as u128) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_sorted, is_permutation_of, is_sorted_ascending, is_sorted_by, is_sorted_descending,
+        new_vec, par_check_sorted, par_is_permutation_of, par_is_sorted_ascending,
+        par_is_sorted_by, par_is_sorted_descending, Distribution, Distribution::*,
+    };
+    use std::collections::HashSet;
+
+    const DISTRIBUTIONS: [Distribution; 8] = [
+        Uniform,
+        Sorted,
+        Reversed,
+        NearlySorted { swaps: 10 },
+        FewUnique { values: 5 },
+        Zipf {
+            values: 100,
+            exponent: 1.2,
+        },
+        OrganPipe,
+        Sawtooth { period: 7 },
+    ];
+
+    #[test]
+    fn same_seed_same_output() {
+        for &distribution in DISTRIBUTIONS.iter() {
+            let a: Vec<u32> = new_vec(1000, distribution, 42);
+            let b: Vec<u32> = new_vec(1000, distribution, 42);
+            assert_eq!(a, b, "{:?}", distribution);
+            let a: Vec<i16> = new_vec(1000, distribution, 42);
+            let b: Vec<i16> = new_vec(1000, distribution, 42);
+            assert_eq!(a, b, "{:?}", distribution);
+        }
+        let a: Vec<u32> = new_vec(1000, Uniform, 1);
+        let b: Vec<u32> = new_vec(1000, Uniform, 2);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn sorted_and_reversed_are_ordered() {
+        // 型の範囲より要素数が多いu8でも、順序は崩れない
+        for &n in [0, 1, 2, 100, 1000].iter() {
+            assert!(is_sorted_ascending(&new_vec::<u8>(n, Sorted, 0)));
+            assert!(is_sorted_descending(&new_vec::<u8>(n, Reversed, 0)));
+            assert!(is_sorted_ascending(&new_vec::<i32>(n, Sorted, 0)));
+            assert!(is_sorted_descending(&new_vec::<i64>(n, Reversed, 0)));
+            assert!(is_sorted_ascending(&new_vec::<u64>(n, Sorted, 0)));
+        }
+        // 1000要素のi32なら、値はすべて異なる
+        let x: Vec<i32> = new_vec(1000, Sorted, 0);
+        assert!(x.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn few_unique_has_at_most_values() {
+        for &values in [1, 2, 5, 300].iter() {
+            let x: Vec<u16> = new_vec(10_000, FewUnique { values }, 3);
+            let distinct: HashSet<u16> = x.into_iter().collect();
+            assert!(distinct.len() <= values, "values: {}", values);
+        }
+        let x: Vec<u32> = new_vec(10_000, FewUnique { values: 5 }, 3);
+        assert_eq!(x.into_iter().collect::<HashSet<_>>().len(), 5);
+    }
+
+    #[test]
+    fn signed_types_cover_min_and_max() {
+        // 要素数が型の値の数と等しければ、すべての値がちょうど1回ずつ現れる
+        let x: Vec<i8> = new_vec(256, Sorted, 0);
+        assert_eq!(x, (i8::MIN..=i8::MAX).collect::<Vec<_>>());
+        let x: Vec<i16> = new_vec(1 << 16, Reversed, 0);
+        assert_eq!((x[0], x[x.len() - 1]), (i16::MAX, i16::MIN));
+
+        let x: Vec<i8> = new_vec(10_000, Uniform, 5);
+        assert!(x.contains(&i8::MIN) && x.contains(&i8::MAX));
+        assert!(x.iter().any(|&v| v < 0) && x.iter().any(|&v| v > 0));
+        let x: Vec<i64> = new_vec(1000, Uniform, 5);
+        assert!(x.iter().any(|&v| v < 0) && x.iter().any(|&v| v > 0));
+    }
+
+    #[test]
+    fn organ_pipe_and_zipf_shapes() {
+        for &n in [1, 2, 9, 10, 1001].iter() {
+            let x: Vec<i32> = new_vec(n, OrganPipe, 0);
+            let mid = n.div_ceil(2);
+            assert!(is_sorted_ascending(&x[..mid]), "n: {}", n);
+            assert!(is_sorted_descending(&x[mid - 1..]), "n: {}", n);
+            // 左右対称になる
+            assert!(x.iter().eq(x.iter().rev()), "n: {}", n);
+        }
+
+        let x: Vec<u32> = new_vec(10, Sawtooth { period: 4 }, 0);
+        assert_eq!(x[..4], x[4..8]);
+        assert!(is_sorted_ascending(&x[..4]));
+
+        // 小さい値ほど多く現れる
+        // exponentが1なら、順位1から4の頻度はおよそ48%, 24%, 16%, 12%
+        let x: Vec<u32> = new_vec(
+            100_000,
+            Zipf {
+                values: 4,
+                exponent: 1.0,
+            },
+            9,
+        );
+        let mut distinct: Vec<u32> = x
+            .iter()
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        distinct.sort();
+        assert_eq!(distinct.len(), 4);
+        let counts: Vec<usize> = distinct
+            .iter()
+            .map(|v| x.iter().filter(|&w| w == v).count())
+            .collect();
+        assert!(
+            counts.windows(2).all(|pair| pair[0] > pair[1]),
+            "{:?}",
+            counts
+        );
+        assert!(counts[0] > 45_000 && counts[0] < 51_000, "{:?}", counts);
+    }
+
+    #[test]
+    fn check_sorted_finds_first_violation() {
+        let ascending = |a: &i32, b: &i32| a.cmp(b);
+        // 順序が崩れている位置は4 (5 > 3) と7 (9 > 0) と9 (8 > 1)
+        let x = [1, 2, 2, 5, 3, 4, 9, 0, 8, 1];
+        assert_eq!(check_sorted(&x, &ascending), Err(4));
+        assert_eq!(par_check_sorted(&x, &ascending), Err(4));
+        assert!(!is_sorted_by(&x, &ascending));
+        assert!(!par_is_sorted_by(&x, &ascending));
+
+        // 並列版でも、いくつにも分割される長さで最初の位置を返す
+        let mut y: Vec<u32> = new_vec(100_000, Sorted, 0);
+        for &i in [99_000, 70_000, 12_345].iter() {
+            y[i] = 0;
+        }
+        let ascending = |a: &u32, b: &u32| a.cmp(b);
+        assert_eq!(check_sorted(&y, &ascending), Err(12_345));
+        assert_eq!(par_check_sorted(&y, &ascending), Err(12_345));
+
+        // 比較関数の順序で判定する
+        let descending = |a: &i32, b: &i32| b.cmp(a);
+        let z = [9, 7, 7, 3, 4];
+        assert_eq!(check_sorted(&z, &descending), Err(4));
+        assert_eq!(par_check_sorted(&z, &descending), Err(4));
+        assert_eq!(check_sorted(&z[..4], &descending), Ok(()));
+        assert_eq!(par_check_sorted(&z[..4], &descending), Ok(()));
+    }
+
+    #[test]
+    fn sequential_and_parallel_checks_agree() {
+        let ascending = |a: &i32, b: &i32| a.cmp(b);
+        let inputs: [&[i32]; 6] = [&[], &[1], &[1, 1], &[2, 1], &[1, 2, 3], &[3, 1, 2]];
+        for &x in inputs.iter() {
+            assert_eq!(check_sorted(x, &ascending), par_check_sorted(x, &ascending));
+            assert_eq!(is_sorted_by(x, &ascending), par_is_sorted_by(x, &ascending));
+            assert_eq!(is_sorted_ascending(x), par_is_sorted_ascending(x));
+            assert_eq!(is_sorted_descending(x), par_is_sorted_descending(x));
+            assert_eq!(is_permutation_of(x, x), par_is_permutation_of(x, x));
+        }
+        let empty: [i32; 0] = [];
+        assert_eq!(check_sorted(&empty, &ascending), Ok(()));
+        assert_eq!(par_check_sorted(&[1], &ascending), Ok(()));
+        assert!(is_permutation_of(&empty, &empty) && par_is_permutation_of(&empty, &empty));
+        assert!(!is_permutation_of(&empty, &[1]) && !par_is_permutation_of(&empty, &[1]));
+    }
+
+    #[test]
+    fn permutation_detects_lost_elements() {
+        let original: Vec<u32> = new_vec(10_000, FewUnique { values: 50 }, 7);
+        let mut sorted = original.clone();
+        sorted.sort();
+        assert!(is_permutation_of(&original, &sorted));
+        assert!(par_is_permutation_of(&original, &sorted));
+
+        // 要素が1つ失われた
+        let dropped = &sorted[1..];
+        assert!(!is_permutation_of(&original, dropped));
+        assert!(!par_is_permutation_of(&original, dropped));
+
+        // 要素が1つ重複した
+        let mut duplicated = sorted.clone();
+        duplicated.push(sorted[0]);
+        assert!(!is_permutation_of(&original, &duplicated));
+        assert!(!par_is_permutation_of(&original, &duplicated));
+
+        // 長さは同じで、1つの値が別の値に置き換わった
+        let mut replaced = sorted.clone();
+        replaced[5000] = replaced[0];
+        assert_ne!(sorted[5000], sorted[0]);
+        assert!(!is_permutation_of(&original, &replaced));
+        assert!(!par_is_permutation_of(&original, &replaced));
+    }
+}
@@ -0,0 +1,166 @@
+// 0-1原理による比較器ネットワークの検証
+// 0と1だけからなる2^n通りの入力をすべてソートできるネットワークは、
+// どんな入力でもソートできる(0-1原理)
+// u64の各ビットを別々の入力とみなし、64通りの入力をまとめて評価する
+
+use super::network::Network;
+use super::SortOrder;
+
+// ネットワークがソートできなかった入力と、そのときの出力
+// 値はどちらも0か1で、添字は線の番号
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counterexample {
+    pub input: Vec<u8>,
+    pub output: Vec<u8>,
+}
+
+// 線0が最も前になるようにソートできるか、0と1のすべての入力で確かめる
+// 反例があれば、入力を2進数とみなしたときに最も小さいものを返す
+// 入力は2^n通りあるので、nが小さいネットワークにしか使えない
+pub fn verify(network: &Network) -> Result<(), Counterexample> {
+    let len = network.len();
+    assert!(len < 64, "too many wires to verify: {}", len);
+    let total: u64 = 1 << len;
+    let mut wires = vec![0u64; len];
+    // base番目からbase + 63番目までの入力をまとめて評価する
+    let mut base = 0;
+    while base < total {
+        for (w, word) in wires.iter_mut().enumerate() {
+            *word = input_word(base, w);
+        }
+        for c in network.comparators() {
+            // 0と1の比較交換は、小さい方がAND、大きい方がORになる
+            let (a, b) = (wires[c.i], wires[c.j]);
+            let (min, max) = (a & b, a | b);
+            match c.direction {
+                SortOrder::Ascending => {
+                    wires[c.i] = min;
+                    wires[c.j] = max;
+                }
+                SortOrder::Descending => {
+                    wires[c.i] = max;
+                    wires[c.j] = min;
+                }
+            }
+        }
+        // 隣り合う線で1の後に0が来ている入力のビットを集める
+        let mut unsorted = wires
+            .windows(2)
+            .fold(0, |bits, pair| bits | (pair[0] & !pair[1]));
+        if total - base < 64 {
+            // 入力が64通りに満たないときは、存在しない入力のビットを落とす
+            unsorted &= (1 << (total - base)) - 1;
+        }
+        if unsorted != 0 {
+            let input = base + u64::from(unsorted.trailing_zeros());
+            return Err(counterexample(network, input));
+        }
+        base += 64;
+    }
+    Ok(())
+}
+
+// 入力番号base + b(0 <= b < 64)の線wの値を、ビットbに並べたワード
+// 入力番号のwビット目を線wの値とする
+fn input_word(base: u64, w: usize) -> u64 {
+    // baseは64の倍数なので、下位6ビットは決まった模様になる
+    const PATTERNS: [u64; 6] = [
+        0xAAAA_AAAA_AAAA_AAAA,
+        0xCCCC_CCCC_CCCC_CCCC,
+        0xF0F0_F0F0_F0F0_F0F0,
+        0xFF00_FF00_FF00_FF00,
+        0xFFFF_0000_FFFF_0000,
+        0xFFFF_FFFF_0000_0000,
+    ];
+    if w < 6 {
+        PATTERNS[w]
+    } else if (base >> w) & 1 == 1 {
+        !0
+    } else {
+        0
+    }
+}
+
+// 反例の入力を1通りだけ評価し直して出力を求める
+fn counterexample(network: &Network, input: u64) -> Counterexample {
+    let input: Vec<u8> = (0..network.len())
+        .map(|w| ((input >> w) & 1) as u8)
+        .collect();
+    let mut output = input.clone();
+    for c in network.comparators() {
+        let swap = match c.direction {
+            SortOrder::Ascending => output[c.i] > output[c.j],
+            SortOrder::Descending => output[c.i] < output[c.j],
+        };
+        if swap {
+            output.swap(c.i, c.j);
+        }
+    }
+    Counterexample { input, output }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify, Counterexample};
+    use crate::network::{Comparator, Network};
+    use crate::SortOrder::*;
+
+    #[test]
+    fn bitonic_networks_sort() {
+        for len in 0..=12 {
+            assert_eq!(verify(&Network::bitonic(len)), Ok(()), "len: {}", len);
+        }
+    }
+
+    #[test]
+    fn broken_network_has_counterexample() {
+        // 最後の比較器(2, 3)を取り除くと、線2と線3の順が直らない入力が残る
+        let mut comparators = Network::bitonic(4).comparators().to_vec();
+        comparators.pop();
+        let network = Network::from_comparators(4, comparators);
+        assert_eq!(
+            verify(&network),
+            Err(Counterexample {
+                input: vec![1, 0, 0, 0],
+                output: vec![0, 0, 1, 0],
+            })
+        );
+    }
+
+    #[test]
+    fn descending_comparators() {
+        // 逆向きの比較器だけのネットワークは、大きい方を前に並べてしまう
+        let network = Network::from_comparators(
+            2,
+            vec![Comparator {
+                i: 0,
+                j: 1,
+                direction: Descending,
+            }],
+        );
+        assert_eq!(
+            verify(&network),
+            Err(Counterexample {
+                input: vec![1, 0],
+                output: vec![1, 0],
+            })
+        );
+    }
+
+    #[test]
+    fn verify_more_than_64_inputs() {
+        // 入力がu64の1ワードに収まらない長さでも、後ろのワードの反例を見つける
+        // 線4と線5の最初の比較器を取り除くと、最初の反例は80番目の入力になる
+        let mut comparators = Network::bitonic(8).comparators().to_vec();
+        let removed = comparators.remove(6);
+        assert_eq!((removed.i, removed.j), (4, 5));
+        let network = Network::from_comparators(8, comparators);
+        assert_eq!(
+            verify(&network),
+            Err(Counterexample {
+                input: vec![0, 0, 0, 0, 1, 0, 1, 0],
+                output: vec![0, 0, 0, 0, 0, 1, 0, 1],
+            })
+        );
+    }
+}